@@ -0,0 +1,139 @@
+/*
+MIT License
+
+Copyright (c) 2023 Vincent Hiribarren
+
+Permission is hereby granted, free of charge, to any person obtaining a copy
+of this software and associated documentation files (the "Software"), to deal
+in the Software without restriction, including without limitation the rights
+to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+copies of the Software, and to permit persons to whom the Software is
+furnished to do so, subject to the following conditions:
+
+The above copyright notice and this permission notice shall be included in all
+copies or substantial portions of the Software.
+
+THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+SOFTWARE.
+*/
+
+use nannou::{
+    color::{Hsv, IntoLinSrgba, LinSrgba, Srgb},
+    prelude::PI,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::Radian;
+
+const VIRIDIS: [[u8; 3]; 9] = [
+    [68, 1, 84],
+    [72, 40, 120],
+    [62, 73, 137],
+    [49, 104, 142],
+    [38, 130, 142],
+    [31, 158, 137],
+    [53, 183, 121],
+    [110, 206, 88],
+    [253, 231, 37],
+];
+
+const MAGMA: [[u8; 3]; 9] = [
+    [0, 0, 4],
+    [28, 16, 68],
+    [79, 18, 123],
+    [129, 37, 129],
+    [181, 54, 122],
+    [229, 80, 100],
+    [251, 135, 97],
+    [254, 194, 135],
+    [252, 253, 191],
+];
+
+const PLASMA: [[u8; 3]; 9] = [
+    [13, 8, 135],
+    [76, 2, 161],
+    [126, 3, 168],
+    [169, 35, 149],
+    [204, 71, 120],
+    [230, 108, 92],
+    [248, 149, 64],
+    [253, 197, 39],
+    [240, 249, 33],
+];
+
+const TURBO: [[u8; 3]; 11] = [
+    [48, 18, 59],
+    [74, 88, 221],
+    [47, 157, 245],
+    [39, 215, 196],
+    [77, 248, 132],
+    [149, 251, 81],
+    [222, 221, 50],
+    [255, 164, 35],
+    [246, 95, 24],
+    [186, 34, 8],
+    [144, 12, 0],
+];
+
+/// Maps values from 0 to 1 to colors.
+#[derive(PartialEq, Debug, Clone, Copy, Serialize, Deserialize)]
+pub enum Colormap {
+    Gray,
+    /// Goes once around the color wheel, the only cyclic colormap.
+    Hsv,
+    Viridis,
+    Magma,
+    Plasma,
+    Turbo,
+}
+
+impl Colormap {
+    pub const ALL: [Colormap; 6] = [
+        Colormap::Gray,
+        Colormap::Hsv,
+        Colormap::Viridis,
+        Colormap::Magma,
+        Colormap::Plasma,
+        Colormap::Turbo,
+    ];
+
+    /// Color for `t`, clamped between 0 and 1.
+    pub fn sample(&self, t: f32) -> LinSrgba {
+        let t = t.clamp(0.0, 1.0);
+        match self {
+            Colormap::Gray => Srgb::new(t, t, t).into_lin_srgba(),
+            Colormap::Hsv => Hsv::new(t * 360.0, 1.0, 1.0).into_lin_srgba(),
+            Colormap::Viridis => interpolate(&VIRIDIS, t),
+            Colormap::Magma => interpolate(&MAGMA, t),
+            Colormap::Plasma => interpolate(&PLASMA, t),
+            Colormap::Turbo => interpolate(&TURBO, t),
+        }
+    }
+
+    /// Color for a direction, so that close angles get close colors.
+    ///
+    /// The cyclic colormap goes once around over a full turn, the others go back and forth with
+    /// the cosine of the angle.
+    pub fn sample_angle(&self, angle: Radian) -> LinSrgba {
+        match self {
+            Colormap::Hsv => self.sample((angle / (2. * PI)).rem_euclid(1.0)),
+            _ => self.sample((angle.cos() + 1.0) / 2.0),
+        }
+    }
+}
+
+/// Linear interpolation between the evenly spaced colors of `table`.
+fn interpolate(table: &[[u8; 3]], t: f32) -> LinSrgba {
+    let position = t * (table.len() - 1) as f32;
+    let index = (position as usize).min(table.len() - 2);
+    let fraction = position - index as f32;
+    let [r0, g0, b0] = table[index];
+    let [r1, g1, b1] = table[index + 1];
+    let channel = |from: u8, to: u8| (from as f32 + (to as f32 - from as f32) * fraction) / 255.0;
+    Srgb::new(channel(r0, r1), channel(g0, g1), channel(b0, b1)).into_lin_srgba()
+}
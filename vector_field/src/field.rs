@@ -0,0 +1,470 @@
+/*
+MIT License
+
+Copyright (c) 2023 Vincent Hiribarren
+
+Permission is hereby granted, free of charge, to any person obtaining a copy
+of this software and associated documentation files (the "Software"), to deal
+in the Software without restriction, including without limitation the rights
+to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+copies of the Software, and to permit persons to whom the Software is
+furnished to do so, subject to the following conditions:
+
+The above copyright notice and this permission notice shall be included in all
+copies or substantial portions of the Software.
+
+THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+SOFTWARE.
+*/
+
+use std::f32::consts::PI;
+
+use evalexpr::{Context, EvalexprError, EvalexprResult, Node, Value};
+use nannou::geom::{Rect, Vec2};
+use rayon::prelude::*;
+
+use crate::{
+    noise::{Fbm, FieldNoise, NoiseDimensions},
+    Radian,
+};
+
+/// Offset, in normalized units, of the noise samples warping the field, far enough for them
+/// to be unrelated to the field itself.
+const WARP_OFFSET: f32 = 17.3;
+
+/// Parameters shared by every sampling of the field during a frame, so that the arrows and
+/// the particles follow the same field.
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub struct FieldParams {
+    pub frequency: f32,
+    /// Angle reached when the noise is 1.
+    pub max_angle: Radian,
+    /// Time coordinate of the animation.
+    pub z: f32,
+    pub orientation: Orientation,
+    pub viewport: Viewport,
+}
+
+impl Default for FieldParams {
+    /// A whole turn at frequency 1, still at time 0, with the default orientation and viewport.
+    fn default() -> Self {
+        FieldParams {
+            frequency: 1.0,
+            max_angle: 2.0 * PI,
+            z: 0.0,
+            orientation: Orientation::default(),
+            viewport: Viewport::default(),
+        }
+    }
+}
+
+/// Way the normalized coordinates of the field grow on screen.
+///
+/// By default `x` grows toward the left of the container and `y` toward its bottom, as the
+/// Perlin coordinates always did. Flipping an axis makes it grow toward the right, or the top.
+#[derive(PartialEq, Debug, Clone, Copy, Default)]
+pub struct Orientation {
+    pub flip_x: bool,
+    pub flip_y: bool,
+}
+
+impl Orientation {
+    /// Maps normalized coordinates in the default orientation to this one.
+    pub fn apply(self, x: f32, y: f32) -> (f32, f32) {
+        (
+            if self.flip_x { 1.0 - x } else { x },
+            if self.flip_y { 1.0 - y } else { y },
+        )
+    }
+
+    /// Screen direction in which each normalized coordinate grows, -1 being the left or the
+    /// bottom.
+    pub fn screen_direction(self) -> Vec2 {
+        Vec2::new(
+            if self.flip_x { 1.0 } else { -1.0 },
+            if self.flip_y { 1.0 } else { -1.0 },
+        )
+    }
+}
+
+/// Zoom and pan of the field in its container.
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub struct Viewport {
+    /// Offset of the field, in container sizes, growing toward the right and the top.
+    pub pan: Vec2,
+    /// Magnification of the field around the container center.
+    pub zoom: f32,
+}
+
+impl Default for Viewport {
+    fn default() -> Self {
+        Viewport {
+            pan: Vec2::ZERO,
+            zoom: 1.0,
+        }
+    }
+}
+
+impl Viewport {
+    /// Position of the unzoomed and unpanned field shown at `position`, both positions in
+    /// `container`.
+    pub fn apply(self, container: Rect, position: Vec2) -> Vec2 {
+        container.xy() + (position - container.xy()) / self.zoom - self.pan * container.wh()
+    }
+}
+
+/// A vector field, given as the angle of its vectors.
+///
+/// `x` and `y` are normalized coordinates in the window, from 0 to 1, growing on screen as
+/// told by `FieldParams::orientation`.
+pub trait FieldSource: Sync {
+    fn angle_at(&self, x: f32, y: f32, params: &FieldParams) -> Radian;
+}
+
+/// Normalized coordinates of `position`, a position in `container`, as taken by `FieldSource`.
+pub fn normalized_position(container: Rect, position: Vec2, params: &FieldParams) -> Vec2 {
+    let position = params.viewport.apply(container, position);
+    let (x, y) = params.orientation.apply(
+        (container.right() - position.x) / container.w(),
+        (container.top() - position.y) / container.h(),
+    );
+    Vec2::new(x, y)
+}
+
+/// Angle of `field` at `position`, a position in `container`.
+///
+/// The arrows and the particles both sample the field through it, so that they cannot disagree
+/// on where a position lies in the field.
+pub fn field_angle(
+    field: &dyn FieldSource,
+    container: Rect,
+    position: Vec2,
+    params: &FieldParams,
+) -> Radian {
+    let normalized = normalized_position(container, position, params);
+    field.angle_at(normalized.x, normalized.y, params)
+}
+
+/// Curve reshaping the noise, from -1 to 1, before it is scaled to an angle.
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum RemapCurve {
+    Linear,
+    /// Smoothstep over the whole range, pushing the values toward the extremes.
+    Smoothstep,
+    /// Signed power, exponents below 1 pushing the values toward the extremes and exponents
+    /// above 1 toward the middle.
+    Power(f32),
+}
+
+impl RemapCurve {
+    pub fn apply(self, value: f32) -> f32 {
+        match self {
+            RemapCurve::Linear => value,
+            RemapCurve::Smoothstep => {
+                let t = ((value + 1.0) / 2.0).clamp(0.0, 1.0);
+                t * t * (3.0 - 2.0 * t) * 2.0 - 1.0
+            }
+            RemapCurve::Power(exponent) => value.signum() * value.abs().powf(exponent),
+        }
+    }
+}
+
+/// Coordinates the noise is sampled in.
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum Domain {
+    Cartesian,
+    /// Distance to the window center and angle around it, giving radial and spiral structures.
+    /// The angle is folded across the horizontal axis, mirroring the halves of the window, for
+    /// the field not to break where it wraps around.
+    Polar,
+}
+
+impl Domain {
+    /// Maps normalized window coordinates to the coordinates of the domain.
+    pub fn apply(self, x: f32, y: f32) -> (f32, f32) {
+        match self {
+            Domain::Cartesian => (x, y),
+            Domain::Polar => {
+                let (dx, dy) = (x - 0.5, y - 0.5);
+                let theta = dy.atan2(dx).abs() / PI;
+                (dx.hypot(dy), theta)
+            }
+        }
+    }
+}
+
+/// Range of the values of a noise, as observed over a frame.
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub struct NoiseRange {
+    pub min: f32,
+    pub max: f32,
+}
+
+impl NoiseRange {
+    /// Widens the range to `observed` at once, and shrinks it toward it by `decay`, the
+    /// fraction of the gap closed from 0 to 1.
+    pub fn track(&mut self, observed: NoiseRange, decay: f32) {
+        self.min = observed
+            .min
+            .min(self.min + (observed.min - self.min) * decay);
+        self.max = observed
+            .max
+            .max(self.max + (observed.max - self.max) * decay);
+    }
+
+    /// Rescales `value` from the range to -1 to 1, leaving it untouched when the range is empty.
+    pub fn normalize(&self, value: f32) -> f32 {
+        if self.max - self.min < f32::EPSILON {
+            return value;
+        }
+        (value - self.min) / (self.max - self.min) * 2.0 - 1.0
+    }
+}
+
+/// Field following a 2D or 4D noise function, sampled with fractal Brownian motion.
+///
+/// In 4D, the time is the fourth coordinate of the noise, the third one selecting a slice of it.
+pub struct NoiseField<'a> {
+    pub noise: &'a dyn FieldNoise,
+    pub dimensions: NoiseDimensions,
+    pub fbm: Fbm,
+    pub depth: f64,
+    /// Scale of the domain warping, the coordinates being offset by other samples of the noise
+    /// before sampling it. 0 disables it.
+    pub warp: f32,
+    pub domain: Domain,
+    /// Range of the noise rescaled to -1 to 1 before the remapping, if any.
+    pub range: Option<NoiseRange>,
+    pub remap: RemapCurve,
+}
+
+impl<'a> NoiseField<'a> {
+    /// The plain 4D `noise`, on a cartesian domain, without octaves, warp nor remapping.
+    pub fn new(noise: &'a dyn FieldNoise) -> Self {
+        NoiseField {
+            noise,
+            dimensions: NoiseDimensions::Four,
+            fbm: Fbm::default(),
+            depth: 0.0,
+            warp: 0.0,
+            domain: Domain::Cartesian,
+            range: None,
+            remap: RemapCurve::Linear,
+        }
+    }
+
+    /// Value of the noise at `x` and `y`, warped but neither rescaled nor remapped.
+    pub fn value_at(&self, x: f32, y: f32, params: &FieldParams) -> f32 {
+        let (x, y) = self.domain.apply(x, y);
+        let (x, y) = if self.warp == 0.0 {
+            (x, y)
+        } else {
+            (
+                x + self.warp * self.sample(x + WARP_OFFSET, y, params),
+                y + self.warp * self.sample(x, y + WARP_OFFSET, params),
+            )
+        };
+        self.sample(x, y, params)
+    }
+
+    fn sample(&self, x: f32, y: f32, params: &FieldParams) -> f32 {
+        match self.dimensions {
+            NoiseDimensions::Two => self.fbm.sample_2d(self.noise, x, y, params.frequency) as f32,
+            NoiseDimensions::Four => self.fbm.sample(
+                self.noise,
+                x,
+                y,
+                self.depth,
+                params.z as f64,
+                params.frequency,
+            ) as f32,
+        }
+    }
+}
+
+impl FieldSource for NoiseField<'_> {
+    fn angle_at(&self, x: f32, y: f32, params: &FieldParams) -> Radian {
+        let value = self.value_at(x, y, params);
+        let value = match self.range {
+            Some(range) => range.normalize(value),
+            None => value,
+        };
+        self.remap.apply(value) * params.max_angle
+    }
+}
+
+/// Linear interpolation between the angles of two fields.
+pub struct BlendField<'a> {
+    pub from: &'a dyn FieldSource,
+    pub to: &'a dyn FieldSource,
+    /// Weight of `to`, from 0 to 1.
+    pub blend: f32,
+}
+
+impl FieldSource for BlendField<'_> {
+    fn angle_at(&self, x: f32, y: f32, params: &FieldParams) -> Radian {
+        let from = self.from.angle_at(x, y, params);
+        if self.blend == 0.0 {
+            return from;
+        }
+        from + (self.to.angle_at(x, y, params) - from) * self.blend
+    }
+}
+
+/// Angles of a field baked over a grid, interpolated between the nodes, so that sampling it
+/// costs the same whatever the field.
+///
+/// The nodes span the normalized coordinates from 0 to 1. The angles are interpolated as they
+/// are, so fields wrapping around, e.g. the `atan2` of an expression, blur at the seams.
+pub struct FieldGrid {
+    columns: usize,
+    rows: usize,
+    angles: Vec<Radian>,
+}
+
+impl FieldGrid {
+    /// Samples `field` over a grid of `columns` by `rows` nodes, at least 2 each.
+    pub fn bake(
+        field: &dyn FieldSource,
+        params: &FieldParams,
+        columns: usize,
+        rows: usize,
+    ) -> Self {
+        let (columns, rows) = (columns.max(2), rows.max(2));
+        let angles = (0..columns * rows)
+            .into_par_iter()
+            .map(|index| {
+                let x = (index % columns) as f32 / (columns - 1) as f32;
+                let y = (index / columns) as f32 / (rows - 1) as f32;
+                field.angle_at(x, y, params)
+            })
+            .collect();
+        FieldGrid {
+            columns,
+            rows,
+            angles,
+        }
+    }
+
+    fn node(&self, column: usize, row: usize) -> Radian {
+        self.angles[row * self.columns + column]
+    }
+}
+
+impl FieldSource for FieldGrid {
+    /// Bilinear interpolation of the baked angles, the parameters being the baked ones.
+    fn angle_at(&self, x: f32, y: f32, _params: &FieldParams) -> Radian {
+        let x = x.clamp(0.0, 1.0) * (self.columns - 1) as f32;
+        let y = y.clamp(0.0, 1.0) * (self.rows - 1) as f32;
+        let (column, row) = (
+            (x as usize).min(self.columns - 2),
+            (y as usize).min(self.rows - 2),
+        );
+        let (tx, ty) = (x - column as f32, y - row as f32);
+        let bottom = self.node(column, row) * (1.0 - tx) + self.node(column + 1, row) * tx;
+        let top = self.node(column, row + 1) * (1.0 - tx) + self.node(column + 1, row + 1) * tx;
+        bottom * (1.0 - ty) + top * ty
+    }
+}
+
+/// Field given by a math expression of `x`, `y` and `t`, evaluated to an angle in radians.
+///
+/// Unlike the Perlin coordinates, `x` grows toward the right and `y` toward the top of the
+/// window whatever the `Orientation`, both from 0 to 1, and `t` is the time coordinate of
+/// `FieldParams`. The frequency and max angle are ignored. `pi` is defined, as well as the usual
+/// functions: `sin`, `cos`, `tan`, `atan2`, `sqrt`, `abs`, `exp`, `ln`, `min`, `max`, `floor`...
+pub struct ExprField {
+    node: Node,
+}
+
+impl ExprField {
+    /// Parses `expression`, checking that it evaluates to a number.
+    pub fn parse(expression: &str) -> EvalexprResult<Self> {
+        let field = ExprField {
+            node: evalexpr::build_operator_tree(expression)?,
+        };
+        // Unknown identifiers are only detected on evaluation.
+        field.evaluate(0.0, 0.0, 0.0)?;
+        Ok(field)
+    }
+
+    fn evaluate(&self, x: f32, y: f32, t: f32) -> EvalexprResult<f64> {
+        self.node.eval_number_with_context(&Variables {
+            x: Value::Float(1.0 - x as f64),
+            y: Value::Float(1.0 - y as f64),
+            t: Value::Float(t as f64),
+            pi: Value::Float(PI as f64),
+        })
+    }
+}
+
+impl FieldSource for ExprField {
+    fn angle_at(&self, x: f32, y: f32, params: &FieldParams) -> Radian {
+        // Flipping the same axes again gives back the default orientation `evaluate` expects.
+        let (x, y) = params.orientation.apply(x, y);
+        // Expressions checked by `parse` can still fail, e.g. on a division by zero.
+        self.evaluate(x, y, params.z).unwrap_or(0.0) as f32
+    }
+}
+
+/// Evaluation context of an `ExprField`.
+struct Variables {
+    x: Value,
+    y: Value,
+    t: Value,
+    pi: Value,
+}
+
+impl Context for Variables {
+    fn get_value(&self, identifier: &str) -> Option<&Value> {
+        match identifier {
+            "x" => Some(&self.x),
+            "y" => Some(&self.y),
+            "t" => Some(&self.t),
+            "pi" => Some(&self.pi),
+            _ => None,
+        }
+    }
+
+    /// Math functions without the `math::` prefix of the evalexpr builtins, which are still
+    /// available.
+    fn call_function(&self, identifier: &str, argument: &Value) -> EvalexprResult<Value> {
+        let function: fn(f64) -> f64 = match identifier {
+            "sin" => f64::sin,
+            "cos" => f64::cos,
+            "tan" => f64::tan,
+            "sqrt" => f64::sqrt,
+            "abs" => f64::abs,
+            "exp" => f64::exp,
+            "ln" => f64::ln,
+            "atan2" => {
+                let arguments = argument.as_fixed_len_tuple(2)?;
+                let (y, x) = (arguments[0].as_number()?, arguments[1].as_number()?);
+                return Ok(Value::Float(y.atan2(x)));
+            }
+            _ => {
+                return Err(EvalexprError::FunctionIdentifierNotFound(
+                    identifier.to_string(),
+                ))
+            }
+        };
+        Ok(Value::Float(function(argument.as_number()?)))
+    }
+
+    fn are_builtin_functions_disabled(&self) -> bool {
+        false
+    }
+
+    fn set_builtin_functions_disabled(&mut self, disabled: bool) -> EvalexprResult<()> {
+        if disabled {
+            Err(EvalexprError::BuiltinFunctionsCannotBeDisabled)
+        } else {
+            Ok(())
+        }
+    }
+}
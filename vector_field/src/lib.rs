@@ -22,6 +22,11 @@ OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 SOFTWARE.
 */
 
+pub mod colormap;
+pub mod field;
+pub mod mask;
+pub mod noise;
 pub mod particles;
+pub mod rust_code;
 
 pub type Radian = f32;
@@ -22,258 +22,3434 @@ OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 SOFTWARE.
 */
 
-use std::rc::Rc;
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    ffi::OsStr,
+    fs, mem,
+    path::{Path, PathBuf},
+    process,
+    sync::{
+        mpsc::{self, Receiver},
+        Arc, Mutex,
+    },
+    time::{SystemTime, UNIX_EPOCH},
+};
 
+use clap::Parser;
+use log::{debug, error, info, warn};
 use nannou::{
-    color::IntoLinSrgba,
     draw::Renderer,
-    noise::{NoiseFn, Perlin},
+    image,
+    noise::{OpenSimplex, Perlin, RangeFunction, Seedable, Value, Worley},
     prelude::*,
+    rand::{rngs::StdRng, seq::SliceRandom, SeedableRng},
 };
 use nannou_egui::{
     egui::{self},
     Egui,
 };
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+use serde::{Deserialize, Serialize};
 use vector_field::{
-    particles::{simple::SimpleParticleSystem, ParticleSystem},
-    Radian,
+    colormap::Colormap,
+    field::{
+        self, normalized_position, BlendField, Domain, ExprField, FieldGrid, FieldParams,
+        FieldSource, NoiseField, NoiseRange, Orientation, RemapCurve, Viewport,
+    },
+    mask::Mask,
+    noise::{Crossfade, Fbm, NoiseDimensions, Rescaled, SharedNoise},
+    particles::{
+        build_particle_system, ForceSource, ParticleBackend, ParticleInfo, ParticleSettings,
+        ParticleSystem,
+    },
+    rust_code, Radian,
 };
 
 const ARROW_COLOR: rgb::Srgb<u8> = BLACK;
 const BACKGROUND_COLOR: rgb::Srgb<u8> = CORNFLOWERBLUE;
+const CONTOUR_COLOR: rgb::Srgb<u8> = ORANGE;
+const CONTOUR_COUNT_DEFAULT: usize = 10;
 const SPEED_DEFAULT: f32 = 0.1;
+const DEPTH_SPEED_DEFAULT: f32 = 0.01;
 const STEP_DEFAULT: usize = 50;
 const MAX_ANGLE_DEFAULT: Radian = 2.0 * PI;
 const RUNNING_DEFAULT: bool = false;
 const SHOW_ARROWS_DEFAULT: bool = true;
 const SHOW_VALUES_DEFAULT: bool = false;
 const FREQUENCY_DEFAULT: f32 = 1.0;
+const REMAP_EXPONENT_DEFAULT: f32 = 2.0;
+const MAX_ANGLE_AMPLITUDE_DEFAULT: Radian = PI / 2.0;
+/// In seconds.
+const MAX_ANGLE_PERIOD_DEFAULT: f32 = 10.0;
+/// Upper bound of the time slider, in noise units.
+const TIME_SCRUB_MAX: f32 = 100.0;
+/// Upper bound of the depth slider, in noise units.
+const DEPTH_MAX: f32 = 10.0;
+const EXPRESSION_DEFAULT: &str = "2 * pi * sin(3 * x + t) * cos(3 * y)";
+const TRAIL_ALPHA_DEFAULT: f32 = 0.05;
+/// In pixels.
+const PUSH_RADIUS_DEFAULT: f32 = 100.0;
+/// Impulse given at the cursor, in pixels per second.
+const PUSH_STRENGTH_DEFAULT: f32 = 300.0;
+/// Key spawning a burst of particles at the cursor.
+const BURST_KEY: Key = Key::B;
+const BURST_COUNT_DEFAULT: usize = 200;
+/// Key saving the frame as the next numbered screenshot.
+const SCREENSHOT_KEY: Key = Key::S;
+/// Key toggling fullscreen, the resize rebuilding the particle texture.
+const FULLSCREEN_KEY: Key = Key::F11;
+/// MSAA sample counts offered for the particle layer.
+const PARTICLE_SAMPLE_COUNTS: [u32; 4] = [1, 2, 4, 8];
+/// Zoom factor of one scroll line.
+const ZOOM_STEP: f32 = 1.1;
+const ZOOM_MIN: f32 = 0.1;
+const ZOOM_MAX: f32 = 100.0;
+/// Number of frames the frame time is averaged over.
+const FRAME_TIME_SAMPLES: usize = 60;
+const STREAMLINE_STEPS_DEFAULT: usize = 30;
+/// Distance covered by one integration step of the streamlines, in pixels.
+const STREAMLINE_STEP_LENGTH: f32 = 5.0;
+/// Seconds between two periodic debug lines.
+const LOG_INTERVAL: f32 = 1.0;
+/// Number of columns of the cached field grid, the rows following the scene aspect ratio.
+const FIELD_GRID_COLUMNS: usize = 256;
+/// Number of samples per side of the grid the noise range is observed over.
+const NOISE_RANGE_SAMPLES: usize = 32;
+/// Fraction of the gap to a narrower observed noise range closed at each frame.
+const NOISE_RANGE_DECAY: f32 = 0.05;
+/// Number of columns of the density histogram, the rows following the window aspect ratio.
+const DENSITY_COLUMNS: usize = 48;
+/// Opacity of the fullest bin of the density histogram.
+const DENSITY_ALPHA_MAX: f32 = 0.6;
+const EXPORT_SUBFRAMES_DEFAULT: usize = 1;
+/// Stroke weight of the arrows, streamlines and critical point markers, in pixels.
+const STROKE_WEIGHT_DEFAULT: f32 = 2.0;
+/// Finite difference step used to derive the field, in normalized units.
+const GRADIENT_EPSILON: f32 = 1e-3;
+/// Angle gradient magnitude, in turns per window, mapped to the top of the magnitude color
+/// scale.
+const MAGNITUDE_MAX: f32 = 2.0;
+/// Time span simulated by `substeps` particle updates, in seconds.
+const SIMULATION_FRAME_DURATION: f32 = 1.0 / 60.0;
+const SUBSTEPS_DEFAULT: usize = 1;
+/// Longest frame time simulated, the rest being dropped not to fall further behind.
+const FRAME_TIME_MAX: f32 = 0.25;
+/// Time span covered by the sub-frames of one exported frame.
+const EXPORT_FRAME_DURATION: f32 = 1.0 / 60.0;
+const INTERNAL_RESOLUTION_DEFAULT: [u32; 2] = [1920, 1080];
+const LETTERBOX_COLOR: rgb::Srgb<u8> = BLACK;
+const SCREENSAVER_INTERVAL_DEFAULT: f32 = 180.0;
+const SCREENSAVER_FADE_DEFAULT: f32 = 10.0;
+const SCREENSAVER_SEEDS_DEFAULT: &str = "1, 2, 3, 5, 8, 13, 21, 34";
+/// Seed of the seed list shuffle, fixed so that the sequence is the same across runs.
+const SCREENSAVER_SHUFFLE_SEED: u64 = 0x5eed;
+const SETTINGS_PATH: &str = "settings.toml";
+/// Directory of the named presets, one settings file each.
+const PRESETS_DIRECTORY: &str = "presets";
+/// Particles to load, one `x,y` or `x,y,r,g,b` line each.
+const PARTICLES_PATH: &str = "particles.csv";
+/// Grayscale image the field is confined to, stretched over the window.
+const MASK_PATH: &str = "mask.png";
+const MASK_THRESHOLD_DEFAULT: u8 = 128;
+const RECORDING_DIRECTORY: &str = "recording";
+const HEADLESS_OUTPUT_DEFAULT: &str = "field.png";
+
+/// Command line options, overriding the defaults of the GUI.
+#[derive(Parser)]
+struct Args {
+    #[arg(long)]
+    seed: Option<u32>,
+    #[arg(long)]
+    frequency: Option<f32>,
+    /// Angle reached when the noise is 1, in radians.
+    #[arg(long)]
+    max_angle: Option<Radian>,
+    /// Spacing of the arrows, in pixels.
+    #[arg(long)]
+    step: Option<usize>,
+    /// Number of particles.
+    #[arg(long)]
+    particles: Option<usize>,
+    #[arg(long)]
+    speed: Option<f32>,
+    /// Renders the arrow field to a PNG file without opening a window.
+    #[arg(long)]
+    headless: bool,
+    /// Size of the headless render, as WIDTHxHEIGHT.
+    #[arg(long, value_parser = parse_size)]
+    size: Option<[u32; 2]>,
+    /// Path of the headless render.
+    #[arg(long, default_value = HEADLESS_OUTPUT_DEFAULT)]
+    output: String,
+    /// Initial size of the window, as WIDTHxHEIGHT in points.
+    #[arg(long, value_parser = parse_size)]
+    window_size: Option<[u32; 2]>,
+    /// Opens the window in fullscreen.
+    #[arg(long)]
+    fullscreen: bool,
+}
+
+fn parse_size(size: &str) -> Result<[u32; 2], String> {
+    let invalid = || format!("expected WIDTHxHEIGHT, got {}", size);
+    let (width, height) = size.split_once('x').ok_or_else(invalid)?;
+    Ok([
+        width.parse().map_err(|_| invalid())?,
+        height.parse().map_err(|_| invalid())?,
+    ])
+}
 
 fn main() {
-    nannou::app(model).update(update).view(view).run();
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("warn")).init();
+    let args = Args::parse();
+    if args.headless {
+        if let Err(error) = render_headless(&args) {
+            error!("Headless rendering failed: {}", error);
+            process::exit(1);
+        }
+        return;
+    }
+    nannou::app(model)
+        .update(update)
+        .view(view)
+        .exit(exit)
+        .run();
 }
 
 struct Model {
     egui: Egui,
+    /// Durations of the last frames, in seconds.
+    frame_times: VecDeque<f32>,
+    /// App time of the last periodic debug line.
+    last_log_time: f32,
     show_arrows: bool,
+    /// Draws the arrows as plain lines when false, cleaner at high density.
+    arrow_heads: bool,
+    arrow_anchor: ArrowAnchor,
+    vector_mode: VectorMode,
+    /// Draws the arrows over the particle layer instead of beneath it.
+    arrows_on_top: bool,
+    /// Stroke weight of the arrows, streamlines and critical point markers, in pixels.
+    stroke_weight: f32,
     show_values: bool,
+    show_critical_points: bool,
+    /// Draws iso-contours of the field angle, the scalar the field derives from.
+    show_contours: bool,
+    /// Contour levels, evenly spread over the range of the angle in the window.
+    contour_count: usize,
+    contour_color: rgb::Srgb<u8>,
+    /// Scales the arrows by the magnitude of the angle gradient.
+    scale_by_magnitude: bool,
+    /// Angle gradient magnitude, in turns per window, below which arrows are hidden.
+    magnitude_threshold: f32,
+    /// Hides the value cells below `magnitude_threshold` as well.
+    hide_flat_cells: bool,
+    /// Shape out of which the grid cells are hidden and the particles respawned.
+    mask: Option<Mask>,
+    /// Incremented each time the mask or its threshold changes.
+    mask_version: u64,
+    show_streamlines: bool,
+    /// Overlays the number of particles per bin of a coarse grid.
+    show_density: bool,
+    streamline_steps: usize,
+    background_color: rgb::Srgb<u8>,
+    arrow_color: rgb::Srgb<u8>,
     running: bool,
+    /// Time coordinate the field is frozen at, the particles still moving while running.
+    frozen_z: Option<f32>,
     reference_time: f32,
     speed: f32,
+    /// Third coordinate of the noise, the fourth one being the time.
+    depth: f32,
+    /// Speed at which `depth` drifts while running.
+    depth_speed: f32,
     step_sample: usize,
+    grid_mode: GridMode,
     max_angle: Radian,
-    noise: Rc<dyn NoiseFn<[f64; 3]>>,
+    /// Oscillates the max angle around `max_angle`.
+    animate_max_angle: bool,
+    max_angle_amplitude: Radian,
+    /// In seconds.
+    max_angle_period: f32,
+    /// Seconds spent running, driving the max angle oscillation.
+    max_angle_time: f32,
+    noise: SharedNoise,
+    noise_source: NoiseSource,
+    seed: u32,
+    /// Second noise, the field angle being blended toward its own.
+    noise_b: SharedNoise,
+    seed_b: u32,
+    blend: f32,
+    noise_version: u64,
     frequency: f32,
-    particle_system: Box<dyn ParticleSystem>,
+    fbm: Fbm,
+    /// Domain warping of the noise field, see `NoiseField::warp`.
+    warp_strength: f32,
+    /// Reshaping of the noise field, see `NoiseField::remap`.
+    remap_curve: RemapCurve,
+    domain: Domain,
+    /// Way the field coordinates grow on screen, see `Orientation`.
+    orientation: Orientation,
+    viewport: Viewport,
+    /// Last cursor position while dragging with the right button, panning the field.
+    pan_anchor: Option<Vec2>,
+    noise_dimensions: NoiseDimensions,
+    /// Rescales the noise to its observed range, so that `max_angle` is actually reached.
+    normalize_noise: bool,
+    /// Range of the noise tracked over the last frames, while normalizing.
+    noise_range: Option<NoiseRange>,
+    /// Samples the field from a grid baked once per change instead of evaluating it everywhere.
+    cache_field: bool,
+    /// The baked field grid, with what it was baked from.
+    field_grid: Option<(FieldGridKey, FieldGrid)>,
+    /// What the field was last built from besides its parameters, and the version of it given
+    /// to the particle systems.
+    field_source: Option<(FieldSourceKey, u64)>,
+    field_kind: FieldKind,
+    expression: String,
+    /// Last valid parse of `expression`.
+    expression_field: ExprField,
+    expression_error: Option<String>,
+    /// Drawn in order over each other, the first one being the one stored in the settings.
+    particle_systems: Vec<Box<dyn ParticleSystem>>,
+    /// Index of the particle system shown in the GUI.
+    selected_particle_system: usize,
+    /// Particles of each system before the last reset, to undo it.
+    reset_snapshot: Option<Vec<Vec<(Vec2, rgb::Srgb<u8>)>>>,
+    particle_backend: ParticleBackend,
     particle_texture: wgpu::Texture,
+    particle_texture_format: ParticleTextureFormat,
+    /// Samples per texel of the particle layer. Higher counts smooth the particle edges, each
+    /// sample costing the memory of another particle texture.
+    particle_sample_count: u32,
+    /// Texture the particles are drawn into when multisampled, resolved into `particle_texture`.
+    particle_msaa_texture: Option<wgpu::Texture>,
     enable_particles: bool,
     renderer: Renderer,
-    angle_color: AngleColor,
+    colormap: Colormap,
+    value_mode: ValueMode,
+    palette_cycle_speed: f32,
+    palette_offset: Radian,
+    particle_draw_speed: u8,
+    /// Particle updates per simulation frame, each one of a fixed duration.
+    substeps: usize,
+    /// Frame time not simulated yet, in seconds.
+    simulation_lag: f32,
+    trails: bool,
+    trail_alpha: f32,
+    /// Keeps every drawn particle in the particle texture, until it is cleared or rebuilt.
+    persistent_canvas: bool,
+    inspect_mode: bool,
+    inspect_frozen: bool,
+    /// Cursor position while dragging, pushing the particles around it.
+    push_position: Option<Vec2>,
+    /// Last known cursor position, where the divergence and curl are read.
+    cursor: Option<Vec2>,
+    push_radius: f32,
+    push_strength: f32,
+    /// Particles spawned at the cursor on each press of `BURST_KEY`.
+    burst_count: usize,
+    value_grid_texture: bool,
+    value_grid_filter: ValueGridFilter,
+    value_texture: Option<wgpu::Texture>,
+    value_texture_key: Option<ValueGridKey>,
+    rust_snippet: Option<String>,
+    export_requested: bool,
+    export_subframes: usize,
+    export_texture: wgpu::Texture,
+    export_renderer: Renderer,
+    texture_capturer: wgpu::TextureCapturer,
+    fixed_resolution: bool,
+    internal_resolution: [u32; 2],
+    /// Size the window is given by the resize button, in points.
+    window_size: [u32; 2],
+    resolution_fit: ResolutionFit,
+    composite_texture: wgpu::Texture,
+    composite_renderer: Renderer,
+    screensaver: Screensaver,
+    hot_reload: bool,
+    /// Names of the files of the presets directory, without extension.
+    presets: Vec<String>,
+    /// Last preset applied.
+    preset: Option<String>,
+    /// Name the current settings are saved as a preset under.
+    preset_name: String,
+    /// Kept alive for the notifications to be sent to `settings_events`.
+    _settings_watcher: Option<RecommendedWatcher>,
+    settings_events: Receiver<()>,
+    /// Set for the frame to be captured, which is drawn without the GUI.
+    capture_requested: bool,
+    /// Set by `SCREENSHOT_KEY` for the next update to capture the frame as a numbered
+    /// screenshot.
+    screenshot_requested: bool,
+    /// Number of the last screenshot saved.
+    screenshot_index: u32,
+    crop_mode: CropMode,
+    /// Left, top, width and height of the kept region, in pixels from the top left corner.
+    crop_region: [u32; 4],
+    /// Capture written during the last frame, with the region it is cropped to once saved.
+    pending_crop: Option<(String, [u32; 4])>,
+    /// Rect of the settings window during the last update, in points.
+    gui_rect: Option<egui::Rect>,
+    /// Captures every frame, GUI included, into the recording directory.
+    recording: bool,
+    frame_index: usize,
+}
+
+/// Periodically crossfades the field to the next seed of a shuffled list.
+struct Screensaver {
+    enabled: bool,
+    interval: f32,
+    fade: f32,
+    seeds: String,
+    order: Vec<u32>,
+    next_index: usize,
+    switch_time: Option<f32>,
+    fading: Option<(SharedNoise, SharedNoise)>,
+}
+
+/// Tunable parameters, as stored in the settings file.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(default)]
+struct Settings {
+    speed: f32,
+    step_sample: usize,
+    max_angle: Radian,
+    frequency: f32,
+    colormap: Colormap,
+    value_mode: ValueMode,
+    show_arrows: bool,
+    show_values: bool,
+    depth_speed: f32,
+    substeps: usize,
+    stroke_weight: f32,
+    contour_count: usize,
+    streamline_steps: usize,
+    enable_particles: bool,
     particle_draw_speed: u8,
+    trail_alpha: f32,
+    push_radius: f32,
+    push_strength: f32,
+    burst_count: usize,
+    particles: ParticleSettings,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            speed: SPEED_DEFAULT,
+            step_sample: STEP_DEFAULT,
+            max_angle: MAX_ANGLE_DEFAULT,
+            frequency: FREQUENCY_DEFAULT,
+            colormap: Colormap::Gray,
+            value_mode: ValueMode::Angle,
+            show_arrows: SHOW_ARROWS_DEFAULT,
+            show_values: SHOW_VALUES_DEFAULT,
+            depth_speed: DEPTH_SPEED_DEFAULT,
+            substeps: SUBSTEPS_DEFAULT,
+            stroke_weight: STROKE_WEIGHT_DEFAULT,
+            contour_count: CONTOUR_COUNT_DEFAULT,
+            streamline_steps: STREAMLINE_STEPS_DEFAULT,
+            enable_particles: false,
+            particle_draw_speed: 1,
+            trail_alpha: TRAIL_ALPHA_DEFAULT,
+            push_radius: PUSH_RADIUS_DEFAULT,
+            push_strength: PUSH_STRENGTH_DEFAULT,
+            burst_count: BURST_COUNT_DEFAULT,
+            particles: ParticleSettings::default(),
+        }
+    }
+}
+
+impl Settings {
+    fn from_model(model: &Model) -> Self {
+        Settings {
+            speed: model.speed,
+            step_sample: model.step_sample,
+            max_angle: model.max_angle,
+            frequency: model.frequency,
+            colormap: model.colormap,
+            value_mode: model.value_mode,
+            show_arrows: model.show_arrows,
+            show_values: model.show_values,
+            depth_speed: model.depth_speed,
+            substeps: model.substeps,
+            stroke_weight: model.stroke_weight,
+            contour_count: model.contour_count,
+            streamline_steps: model.streamline_steps,
+            enable_particles: model.enable_particles,
+            particle_draw_speed: model.particle_draw_speed,
+            trail_alpha: model.trail_alpha,
+            push_radius: model.push_radius,
+            push_strength: model.push_strength,
+            burst_count: model.burst_count,
+            particles: model.particle_systems[0].config(),
+        }
+    }
+
+    /// Applies the settings, clamped to the ranges allowed by the GUI.
+    fn apply(&self, model: &mut Model) {
+        model.speed = self.speed.clamp(0.0, 100.0);
+        model.step_sample = self.step_sample.clamp(1, 100);
+        model.max_angle = self.max_angle.clamp(0.0, 2.0 * PI);
+        model.frequency = self.frequency.clamp(0.1, 100.0);
+        model.colormap = self.colormap;
+        model.value_mode = self.value_mode;
+        model.show_arrows = self.show_arrows;
+        model.show_values = self.show_values;
+        model.depth_speed = self.depth_speed.clamp(0.0, 1.0);
+        model.substeps = self.substeps.clamp(1, 16);
+        model.stroke_weight = self.stroke_weight.clamp(0.5, 8.0);
+        model.contour_count = self.contour_count.clamp(1, 50);
+        model.streamline_steps = self.streamline_steps.clamp(1, 500);
+        model.enable_particles = self.enable_particles;
+        model.particle_draw_speed = self.particle_draw_speed.clamp(1, 100);
+        model.trail_alpha = self.trail_alpha.clamp(0.02, 0.2);
+        model.push_radius = self.push_radius.clamp(10.0, 400.0);
+        model.push_strength = self.push_strength.clamp(0.0, 2000.0);
+        model.burst_count = self.burst_count.min(10_000);
+        model.particle_systems[0].apply_config(&self.particles);
+    }
+}
+
+/// How `step_sample` is interpreted.
+#[derive(PartialEq, Debug, Clone, Copy)]
+enum GridMode {
+    /// Distance between two grid nodes, in pixels.
+    Pixels,
+    /// Number of grid columns across the window, keeping the density whatever its size.
+    Columns,
+}
+
+/// Part of the window kept in the PNG captures.
+#[derive(PartialEq, Debug, Clone, Copy)]
+enum CropMode {
+    Full,
+    /// The widest strip of the window left or right of the GUI, over its whole height.
+    ExcludeGui,
+    /// `crop_region`.
+    Region,
+}
+
+/// Where the arrows are attached to their grid node.
+#[derive(PartialEq, Debug, Clone, Copy)]
+enum ArrowAnchor {
+    /// Straddling the node.
+    Center,
+    /// Starting at the node.
+    Base,
+}
+
+/// Vector drawn by the arrows.
+#[derive(PartialEq, Debug, Clone, Copy)]
+enum VectorMode {
+    /// Along the field angle, of unit length unless scaled by magnitude.
+    Angle,
+    /// The gradient of the field angle, as long as its cell at `MAGNITUDE_MAX`.
+    Gradient,
+}
+
+/// Precision of the particle layer texture.
+#[derive(PartialEq, Debug, Clone, Copy)]
+enum ParticleTextureFormat {
+    /// 8 bytes per texel. Trails fade smoothly down to nothing.
+    Float16,
+    /// 4 bytes per texel, lighter on integrated GPUs. Trails band as they fade, and faint ones
+    /// get stuck above 0 as the fade rounds back to the same 8 bit value.
+    Unorm8,
+}
+
+impl ParticleTextureFormat {
+    fn texture_format(self) -> wgpu::TextureFormat {
+        match self {
+            ParticleTextureFormat::Float16 => wgpu::TextureFormat::Rgba16Float,
+            ParticleTextureFormat::Unorm8 => wgpu::TextureFormat::Rgba8Unorm,
+        }
+    }
+}
+
+/// Where the field angles come from.
+#[derive(PartialEq, Debug, Clone, Copy)]
+enum FieldKind {
+    Noise,
+    Expression,
+}
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+enum NoiseSource {
+    Perlin,
+    Simplex,
+    Value,
+    Worley(WorleyDistance),
+}
+
+impl NoiseSource {
+    fn build(self, seed: u32) -> SharedNoise {
+        match self {
+            NoiseSource::Perlin => Arc::new(Perlin::new().set_seed(seed)),
+            NoiseSource::Simplex => Arc::new(OpenSimplex::new().set_seed(seed)),
+            NoiseSource::Value => Arc::new(Value::new().set_seed(seed)),
+            NoiseSource::Worley(distance) => Arc::new(Rescaled {
+                noise: Worley::new()
+                    .set_seed(seed)
+                    .set_range_function(distance.range_function())
+                    .enable_range(true),
+                max: distance.max_output(),
+            }),
+        }
+    }
+}
+
+/// Distance function of the Worley noise, the distance to the nearest cell center giving the
+/// noise value.
+#[derive(PartialEq, Debug, Clone, Copy)]
+enum WorleyDistance {
+    Euclidean,
+    Manhattan,
+    Chebyshev,
+}
+
+impl WorleyDistance {
+    fn range_function(self) -> RangeFunction {
+        match self {
+            WorleyDistance::Euclidean => RangeFunction::Euclidean,
+            WorleyDistance::Manhattan => RangeFunction::Manhattan,
+            WorleyDistance::Chebyshev => RangeFunction::Chebyshev,
+        }
+    }
+
+    /// Upper bound of the Worley noise output, its lower bound being -1, measured by sampling.
+    fn max_output(self) -> f64 {
+        match self {
+            WorleyDistance::Euclidean => 1.0,
+            WorleyDistance::Manhattan => 2.8,
+            WorleyDistance::Chebyshev => 0.55,
+        }
+    }
+}
+
+/// Quantity shown by the value grid.
+#[derive(PartialEq, Debug, Clone, Copy, Serialize, Deserialize)]
+enum ValueMode {
+    /// Direction of the field.
+    Angle,
+    /// Magnitude of the angle gradient.
+    Magnitude,
+}
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+enum ValueGridFilter {
+    Nearest,
+    Bilinear,
 }
 
-#[allow(clippy::upper_case_acronyms)]
+impl ValueGridFilter {
+    fn filter_mode(self) -> wgpu::FilterMode {
+        match self {
+            ValueGridFilter::Nearest => wgpu::FilterMode::Nearest,
+            ValueGridFilter::Bilinear => wgpu::FilterMode::Linear,
+        }
+    }
+}
+
+/// Kind of a critical point of the field, classified from the local Jacobian.
+#[derive(PartialEq, Debug, Clone, Copy)]
+enum CriticalPoint {
+    Source,
+    Sink,
+    Saddle,
+}
+
+impl CriticalPoint {
+    fn color(self) -> rgb::Srgb<u8> {
+        match self {
+            CriticalPoint::Source => RED,
+            CriticalPoint::Sink => BLUE,
+            CriticalPoint::Saddle => YELLOW,
+        }
+    }
+}
+
+/// How the fixed resolution composite is scaled into the window.
+#[derive(PartialEq, Debug, Clone, Copy)]
+enum ResolutionFit {
+    /// Whole composite visible, with letterboxing.
+    Fit,
+    /// Whole window covered, cropping the composite.
+    Fill,
+}
+
+/// Everything the baked value grid texture depends on, used to detect when it must be rebuilt.
+#[derive(PartialEq, Debug)]
+struct ValueGridKey {
+    params: FieldParams,
+    source: FieldSourceKey,
+    step: usize,
+    colormap: Colormap,
+    value_mode: ValueMode,
+    palette_offset: Radian,
+    window_size: [u32; 2],
+    /// Threshold of the hidden cells, if any.
+    flat_threshold: Option<f32>,
+    mask_version: u64,
+}
+
+/// Everything the field depends on besides its `FieldParams`.
+#[derive(PartialEq, Debug)]
+struct FieldSourceKey {
+    noise_version: u64,
+    fbm: Fbm,
+    depth: f32,
+    blend: f32,
+    warp_strength: f32,
+    remap_curve: RemapCurve,
+    domain: Domain,
+    noise_dimensions: NoiseDimensions,
+    noise_range: Option<NoiseRange>,
+}
+
+impl FieldSourceKey {
+    fn from_model(model: &Model) -> Self {
+        FieldSourceKey {
+            noise_version: model.noise_version,
+            fbm: model.fbm,
+            depth: model.depth,
+            blend: model.blend,
+            warp_strength: model.warp_strength,
+            remap_curve: model.remap_curve,
+            domain: model.domain,
+            noise_dimensions: model.noise_dimensions,
+            noise_range: model.noise_range,
+        }
+    }
+}
+
+/// Everything the cached field grid depends on, used to detect when it must be baked again.
 #[derive(PartialEq, Debug)]
-enum AngleColor {
-    Gray,
-    HSV,
+struct FieldGridKey {
+    params: FieldParams,
+    source: FieldSourceKey,
+    size: [usize; 2],
 }
 
 fn model(app: &App) -> Model {
     fn raw_window_event(_app: &App, model: &mut Model, event: &nannou::winit::event::WindowEvent) {
         model.egui.handle_raw_event(event);
     }
+    let args = Args::parse();
     let window = {
-        let window_id = app
-            .new_window()
+        let mut builder = app.new_window();
+        if let Some([width, height]) = args.window_size {
+            builder = builder.size(width, height);
+        }
+        let window_id = builder
             .view(view)
             .raw_event(raw_window_event)
+            .mouse_pressed(mouse_pressed)
+            .mouse_moved(mouse_moved)
+            .mouse_released(mouse_released)
+            .mouse_wheel(mouse_wheel)
+            .key_pressed(key_pressed)
+            .resized(resized)
             .build()
             .unwrap();
         app.window(window_id).unwrap()
     };
+    if args.fullscreen {
+        window.set_fullscreen(true);
+    }
     let egui = Egui::from_window(&window);
-    let noise = Rc::new(Perlin::new());
-    let particle_system = Box::new(SimpleParticleSystem::new(window.rect(), noise.clone()));
-    let particle_texture = wgpu::TextureBuilder::new()
-        .size([window.rect().w() as u32, window.rect().h() as u32])
-        .usage(wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING)
-        .sample_count(1) //.sample_count(window.msaa_samples())
-        .format(wgpu::TextureFormat::Rgba16Float)
-        .build(window.device());
-    let renderer = {
-        let descriptor = particle_texture.descriptor();
-        nannou::draw::RendererBuilder::new()
-            .build_from_texture_descriptor(window.device(), descriptor)
+    let noise_source = NoiseSource::Perlin;
+    let seed = args.seed.unwrap_or(Perlin::DEFAULT_SEED);
+    let noise = noise_source.build(seed);
+    let particle_backend = ParticleBackend::Cpu;
+    let mut particle_system = build_particle_system(
+        particle_backend,
+        window.rect(),
+        seed as u64,
+        window.device_queue_pair(),
+    );
+    if let Some(count) = args.particles {
+        particle_system.set_count(count);
+    }
+    let particle_texture = particle_texture(
+        window.device(),
+        [window.rect().w() as u32, window.rect().h() as u32],
+        ParticleTextureFormat::Float16,
+    );
+    let renderer = texture_renderer(window.device(), &particle_texture);
+    let export_texture = render_texture(
+        window.device(),
+        [window.rect().w() as u32, window.rect().h() as u32],
+    );
+    let export_renderer = texture_renderer(window.device(), &export_texture);
+    let composite_texture = render_texture(window.device(), INTERNAL_RESOLUTION_DEFAULT);
+    let composite_renderer = texture_renderer(window.device(), &composite_texture);
+    let (settings_events_sender, settings_events) = mpsc::channel();
+    let settings_watcher = match watch_settings(settings_events_sender) {
+        Ok(watcher) => Some(watcher),
+        Err(error) => {
+            warn!("Cannot watch {}: {}", SETTINGS_PATH, error);
+            None
+        }
     };
     Model {
         egui,
+        frame_times: VecDeque::with_capacity(FRAME_TIME_SAMPLES),
+        last_log_time: 0.0,
         running: RUNNING_DEFAULT,
+        frozen_z: None,
         show_arrows: SHOW_ARROWS_DEFAULT,
+        arrow_heads: true,
+        arrow_anchor: ArrowAnchor::Center,
+        vector_mode: VectorMode::Angle,
+        arrows_on_top: false,
+        stroke_weight: STROKE_WEIGHT_DEFAULT,
         show_values: SHOW_VALUES_DEFAULT,
+        show_critical_points: false,
+        show_contours: false,
+        contour_count: CONTOUR_COUNT_DEFAULT,
+        contour_color: CONTOUR_COLOR,
+        scale_by_magnitude: false,
+        magnitude_threshold: 0.0,
+        hide_flat_cells: false,
+        mask: None,
+        mask_version: 0,
+        show_streamlines: false,
+        show_density: false,
+        streamline_steps: STREAMLINE_STEPS_DEFAULT,
+        background_color: BACKGROUND_COLOR,
+        arrow_color: ARROW_COLOR,
         reference_time: 0_f32,
-        speed: SPEED_DEFAULT,
-        step_sample: STEP_DEFAULT,
-        max_angle: MAX_ANGLE_DEFAULT,
-        noise: noise.clone(),
-        frequency: FREQUENCY_DEFAULT,
-        particle_system,
+        speed: args.speed.unwrap_or(SPEED_DEFAULT),
+        depth: 0.0,
+        depth_speed: DEPTH_SPEED_DEFAULT,
+        step_sample: args.step.unwrap_or(STEP_DEFAULT).clamp(1, 100),
+        grid_mode: GridMode::Pixels,
+        max_angle: args.max_angle.unwrap_or(MAX_ANGLE_DEFAULT),
+        animate_max_angle: false,
+        max_angle_amplitude: MAX_ANGLE_AMPLITUDE_DEFAULT,
+        max_angle_period: MAX_ANGLE_PERIOD_DEFAULT,
+        max_angle_time: 0.0,
+        noise,
+        noise_source,
+        seed,
+        noise_b: noise_source.build(seed.wrapping_add(1)),
+        seed_b: seed.wrapping_add(1),
+        blend: 0.0,
+        noise_version: 0,
+        frequency: args.frequency.unwrap_or(FREQUENCY_DEFAULT),
+        fbm: Fbm::default(),
+        warp_strength: 0.0,
+        remap_curve: RemapCurve::Linear,
+        domain: Domain::Cartesian,
+        orientation: Orientation::default(),
+        viewport: Viewport::default(),
+        pan_anchor: None,
+        noise_dimensions: NoiseDimensions::Four,
+        normalize_noise: false,
+        noise_range: None,
+        cache_field: false,
+        field_grid: None,
+        field_source: None,
+        field_kind: FieldKind::Noise,
+        expression: EXPRESSION_DEFAULT.to_string(),
+        expression_field: ExprField::parse(EXPRESSION_DEFAULT).unwrap(),
+        expression_error: None,
+        particle_systems: vec![particle_system],
+        selected_particle_system: 0,
+        reset_snapshot: None,
+        particle_backend,
         particle_texture,
+        particle_texture_format: ParticleTextureFormat::Float16,
+        particle_sample_count: 1,
+        particle_msaa_texture: None,
         renderer,
         enable_particles: false,
-        angle_color: AngleColor::Gray,
+        colormap: Colormap::Gray,
+        value_mode: ValueMode::Angle,
+        palette_cycle_speed: 0.0,
+        palette_offset: 0.0,
         particle_draw_speed: 1,
+        substeps: SUBSTEPS_DEFAULT,
+        simulation_lag: 0.0,
+        trails: false,
+        persistent_canvas: false,
+        trail_alpha: TRAIL_ALPHA_DEFAULT,
+        inspect_mode: false,
+        push_position: None,
+        cursor: None,
+        push_radius: PUSH_RADIUS_DEFAULT,
+        push_strength: PUSH_STRENGTH_DEFAULT,
+        burst_count: BURST_COUNT_DEFAULT,
+        inspect_frozen: false,
+        value_grid_texture: false,
+        value_grid_filter: ValueGridFilter::Nearest,
+        value_texture: None,
+        value_texture_key: None,
+        rust_snippet: None,
+        export_requested: false,
+        export_subframes: EXPORT_SUBFRAMES_DEFAULT,
+        export_texture,
+        export_renderer,
+        texture_capturer: wgpu::TextureCapturer::default(),
+        fixed_resolution: false,
+        internal_resolution: INTERNAL_RESOLUTION_DEFAULT,
+        window_size: [window.rect().w() as u32, window.rect().h() as u32],
+        resolution_fit: ResolutionFit::Fit,
+        composite_texture,
+        composite_renderer,
+        screensaver: Screensaver {
+            enabled: false,
+            interval: SCREENSAVER_INTERVAL_DEFAULT,
+            fade: SCREENSAVER_FADE_DEFAULT,
+            seeds: SCREENSAVER_SEEDS_DEFAULT.to_owned(),
+            order: shuffled_seeds(SCREENSAVER_SEEDS_DEFAULT),
+            next_index: 0,
+            switch_time: None,
+            fading: None,
+        },
+        hot_reload: true,
+        presets: list_presets(),
+        preset: None,
+        preset_name: String::new(),
+        _settings_watcher: settings_watcher,
+        settings_events,
+        capture_requested: false,
+        screenshot_requested: false,
+        screenshot_index: 0,
+        crop_mode: CropMode::Full,
+        crop_region: [0, 0, 800, 600],
+        pending_crop: None,
+        gui_rect: None,
+        recording: false,
+        frame_index: 0,
+    }
+}
+
+/// Watches the working directory, notifying `sender` when the settings file is written.
+fn watch_settings(sender: mpsc::Sender<()>) -> notify::Result<RecommendedWatcher> {
+    let mut watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+        let event = match event {
+            Ok(event) => event,
+            Err(_) => return,
+        };
+        let is_settings = event
+            .paths
+            .iter()
+            .any(|path| path.file_name() == Some(OsStr::new(SETTINGS_PATH)));
+        if is_settings && (event.kind.is_create() || event.kind.is_modify()) {
+            // The receiver only disappears when the app exits.
+            let _ = sender.send(());
+        }
+    })?;
+    watcher.watch(Path::new("."), RecursiveMode::NonRecursive)?;
+    Ok(watcher)
+}
+
+fn save_settings(model: &Model, path: &Path) {
+    let written = toml::to_string_pretty(&Settings::from_model(model))
+        .map_err(|error| error.to_string())
+        .and_then(|content| fs::write(path, content).map_err(|error| error.to_string()));
+    match written {
+        Ok(()) => info!("Saved the settings to {}", path.display()),
+        Err(error) => warn!("Cannot save {}: {}", path.display(), error),
+    }
+}
+
+/// Loads and applies a settings file, keeping the current values if it cannot be read.
+fn load_settings(model: &mut Model, path: &Path) {
+    let settings = fs::read_to_string(path)
+        .map_err(|error| error.to_string())
+        .and_then(|content| {
+            toml::from_str::<Settings>(&content).map_err(|error| error.to_string())
+        });
+    match settings {
+        Ok(settings) => {
+            settings.apply(model);
+            info!("Loaded the settings from {}", path.display());
+        }
+        Err(error) => warn!("Cannot load {}: {}", path.display(), error),
+    }
+}
+
+fn preset_path(name: &str) -> PathBuf {
+    Path::new(PRESETS_DIRECTORY).join(format!("{}.toml", name))
+}
+
+/// Names of the presets, sorted, empty when the presets directory cannot be read.
+fn list_presets() -> Vec<String> {
+    let mut presets: Vec<String> = fs::read_dir(PRESETS_DIRECTORY)
+        .map(|entries| {
+            entries
+                .filter_map(|entry| entry.ok())
+                .map(|entry| entry.path())
+                .filter(|path| path.extension() == Some(OsStr::new("toml")))
+                .filter_map(|path| Some(path.file_stem()?.to_str()?.to_owned()))
+                .collect()
+        })
+        .unwrap_or_default();
+    presets.sort();
+    presets
+}
+
+/// Saves the current settings as the preset named `model.preset_name`.
+fn save_preset(model: &mut Model) {
+    let name = model.preset_name.trim().to_owned();
+    if name.is_empty() || name.contains(['/', '\\']) {
+        warn!("Invalid preset name: {:?}", name);
+        return;
+    }
+    if let Err(error) = fs::create_dir_all(PRESETS_DIRECTORY) {
+        warn!("Cannot create {}: {}", PRESETS_DIRECTORY, error);
+        return;
+    }
+    save_settings(model, &preset_path(&name));
+    model.presets = list_presets();
+    model.preset = Some(name);
+}
+
+/// Replaces the particles by the ones listed in the particles file.
+fn load_particles(app: &App, model: &mut Model) {
+    let content = match fs::read_to_string(PARTICLES_PATH) {
+        Ok(content) => content,
+        Err(error) => {
+            warn!("Cannot load {}: {}", PARTICLES_PATH, error);
+            return;
+        }
+    };
+    let (particles, skipped) = parse_particles(&content);
+    if skipped > 0 {
+        warn!("Skipped {} invalid lines of {}", skipped, PARTICLES_PATH);
+    }
+    let win = app.window_rect();
+    let outside = particles
+        .iter()
+        .filter(|(position, _)| !win.contains(*position))
+        .count();
+    if outside > 0 {
+        warn!(
+            "Clamped {} particles of {} into the window",
+            outside, PARTICLES_PATH
+        );
+    }
+    model.particle_systems[model.selected_particle_system].load_positions(particles);
+}
+
+/// Confines the field to the mask file, keeping the threshold of the previous mask.
+fn load_mask(model: &mut Model) {
+    let image = match image::open(MASK_PATH) {
+        Ok(image) => image.to_luma8(),
+        Err(error) => {
+            warn!("Cannot load {}: {}", MASK_PATH, error);
+            return;
+        }
+    };
+    let threshold = model
+        .mask
+        .as_ref()
+        .map_or(MASK_THRESHOLD_DEFAULT, |mask| mask.threshold);
+    model.mask = Some(Mask::new(image, threshold));
+    model.mask_version += 1;
+}
+
+/// Parses `x,y` lines, in window coordinates, optionally followed by `r,g,b` components from 0
+/// to 255. Particles without a color get a random one.
+///
+/// Returns the particles and the number of lines which could not be parsed, a header line
+/// included.
+fn parse_particles(content: &str) -> (Vec<(Vec2, rgb::Srgb<u8>)>, usize) {
+    let mut particles = vec![];
+    let mut skipped = 0;
+    for line in content.lines().filter(|line| !line.trim().is_empty()) {
+        let fields: Vec<&str> = line.split(',').map(str::trim).collect();
+        let position = match fields[..] {
+            [x, y, ..] => x.parse().ok().zip(y.parse().ok()),
+            _ => None,
+        };
+        let color = match fields[..] {
+            [_, _] => Some(rgb::Srgb::new(random(), random(), random())),
+            [_, _, r, g, b] => r
+                .parse()
+                .ok()
+                .zip(g.parse().ok())
+                .zip(b.parse().ok())
+                .map(|((r, g), b)| rgb::Srgb::new(r, g, b)),
+            _ => None,
+        };
+        match (position, color) {
+            (Some((x, y)), Some(color)) => particles.push((Vec2::new(x, y), color)),
+            _ => skipped += 1,
+        }
+    }
+    (particles, skipped)
+}
+
+/// Parses a comma separated list of seeds, and shuffles it in a reproducible order.
+fn shuffled_seeds(seeds: &str) -> Vec<u32> {
+    let mut seeds: Vec<u32> = seeds
+        .split(',')
+        .filter_map(|seed| seed.trim().parse().ok())
+        .collect();
+    seeds.shuffle(&mut StdRng::seed_from_u64(SCREENSAVER_SHUFFLE_SEED));
+    seeds
+}
+
+/// Builds an offscreen texture the scene can be rendered into.
+fn render_texture(device: &wgpu::Device, size: [u32; 2]) -> wgpu::Texture {
+    wgpu::TextureBuilder::new()
+        .size(size)
+        .usage(wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING)
+        .sample_count(1)
+        .format(wgpu::TextureFormat::Rgba16Float)
+        .build(device)
+}
+
+/// Builds the texture the particles are drawn into.
+fn particle_texture(
+    device: &wgpu::Device,
+    size: [u32; 2],
+    format: ParticleTextureFormat,
+) -> wgpu::Texture {
+    wgpu::TextureBuilder::new()
+        .size(size)
+        .usage(wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING)
+        .sample_count(1)
+        .format(format.texture_format())
+        .build(device)
+}
+
+/// Builds the multisampled texture the particles are drawn into before being resolved into the
+/// particle texture, none being needed for a single sample.
+fn particle_msaa_texture(
+    device: &wgpu::Device,
+    size: [u32; 2],
+    format: ParticleTextureFormat,
+    sample_count: u32,
+) -> Option<wgpu::Texture> {
+    (sample_count > 1).then(|| {
+        wgpu::TextureBuilder::new()
+            .size(size)
+            .usage(wgpu::TextureUsages::RENDER_ATTACHMENT)
+            .sample_count(sample_count)
+            .format(format.texture_format())
+            .build(device)
+    })
+}
+
+/// Rebuilds the particle layer at `size`, cleared, with the renderer drawing into it.
+fn rebuild_particle_texture(app: &App, model: &mut Model, size: [u32; 2]) {
+    let window = app.main_window();
+    let device = window.device();
+    let format = model.particle_texture_format;
+    model.particle_texture = particle_texture(device, size, format);
+    model.particle_msaa_texture =
+        particle_msaa_texture(device, size, format, model.particle_sample_count);
+    let target = model
+        .particle_msaa_texture
+        .as_ref()
+        .unwrap_or(&model.particle_texture);
+    model.renderer = texture_renderer(device, target);
+}
+
+fn texture_renderer(device: &wgpu::Device, texture: &wgpu::Texture) -> Renderer {
+    nannou::draw::RendererBuilder::new().build_from_texture_descriptor(device, texture.descriptor())
+}
+
+/// Renders the arrows of the Perlin field to `args.output`, through an off-screen texture,
+/// without any window nor GUI.
+fn render_headless(args: &Args) -> Result<(), String> {
+    let instance = wgpu::Instance::new(wgpu::DEFAULT_BACKENDS);
+    let adapter = wgpu::AdapterMap::default()
+        .get_or_request(
+            wgpu::RequestAdapterOptions {
+                power_preference: wgpu::DEFAULT_POWER_PREFERENCE,
+                force_fallback_adapter: false,
+                compatible_surface: None,
+            },
+            &instance,
+        )
+        .ok_or("no graphics adapter available")?;
+    let device_queue_pair = adapter.get_or_request_device(wgpu::default_device_descriptor());
+    let device = device_queue_pair.device();
+    let size = args.size.unwrap_or(INTERNAL_RESOLUTION_DEFAULT);
+    let texture = render_texture(device, size);
+    let mut renderer = texture_renderer(device, &texture);
+
+    let [width, height] = size;
+    let win = Rect::from_w_h(width as f32, height as f32);
+    let noise = Perlin::new().set_seed(args.seed.unwrap_or(Perlin::DEFAULT_SEED));
+    let field = NoiseField::new(&noise);
+    let params = FieldParams {
+        frequency: args.frequency.unwrap_or(FREQUENCY_DEFAULT),
+        max_angle: args.max_angle.unwrap_or(MAX_ANGLE_DEFAULT),
+        ..FieldParams::default()
+    };
+    let step = args.step.unwrap_or(STEP_DEFAULT).max(1);
+    let draw = Draw::new();
+    draw.background().color(BACKGROUND_COLOR);
+    for canvas_x in (win.left() as i32..win.right() as i32).step_by(step) {
+        for canvas_y in (win.bottom() as i32..win.top() as i32).step_by(step) {
+            let canvas_point = Vec2::new(canvas_x as f32, canvas_y as f32);
+            let angle = field::field_angle(&field, win, canvas_point, &params);
+            let offset = Vec2::new(1., 0.).rotate(angle) * step.saturating_sub(2) as f32 / 2.;
+            draw.arrow()
+                .start(canvas_point - offset)
+                .end(canvas_point + offset)
+                .stroke_weight(STROKE_WEIGHT_DEFAULT)
+                .caps_round()
+                .color(ARROW_COLOR);
+        }
+    }
+
+    let capturer = wgpu::TextureCapturer::default();
+    let ce_desc = wgpu::CommandEncoderDescriptor {
+        label: Some("headless renderer"),
+    };
+    let mut encoder = device.create_command_encoder(&ce_desc);
+    renderer.render_to_texture(device, &mut encoder, &draw, &texture);
+    let snapshot = capturer.capture(device, &mut encoder, &texture);
+    device_queue_pair.queue().submit(Some(encoder.finish()));
+    let output = args.output.clone();
+    // The capture worker reports back whether the image was saved.
+    let (saved_sender, saved) = mpsc::channel();
+    snapshot
+        .read(move |result| {
+            let saved = match result {
+                Ok(image) => image
+                    .to_owned()
+                    .save(&output)
+                    .map_err(|error| format!("cannot save {}: {}", output, error)),
+                Err(error) => Err(format!("failed to map texture memory: {:?}", error)),
+            };
+            // The receiver only hangs up once the result is no longer awaited.
+            let _ = saved_sender.send(saved);
+        })
+        .map_err(|_| "timed out waiting for a capture worker")?;
+    capturer
+        .await_active_snapshots(device)
+        .map_err(|_| "timed out reading the texture back")?;
+    saved
+        .recv()
+        .map_err(|_| "the capture worker stopped before saving".to_string())?
+}
+
+fn exit(app: &App, model: Model) {
+    let window = app.main_window();
+    model
+        .texture_capturer
+        .await_active_snapshots(window.device())
+        .unwrap();
+}
+
+/// Resizes the particle layer to the new window size.
+fn resized(app: &App, model: &mut Model, size: Vec2) {
+    // Minimized windows can report an empty size, which textures cannot have.
+    if size.x < 1.0 || size.y < 1.0 {
+        return;
+    }
+    rebuild_particle_texture(app, model, [size.x as u32, size.y as u32]);
+    info!("Rebuilt the particle texture at {}x{}", size.x, size.y);
+    for particle_system in &mut model.particle_systems {
+        particle_system.set_container(Rect::from_wh(size));
+    }
+}
+
+/// Toggles the inspected particle in inspect mode, starts pushing the particles otherwise.
+/// Starts panning the field with the right button.
+fn mouse_pressed(app: &App, model: &mut Model, button: MouseButton) {
+    if model.egui.ctx().is_pointer_over_area() {
+        return;
+    }
+    if button == MouseButton::Right {
+        model.pan_anchor = Some(app.mouse.position());
+    }
+    if button != MouseButton::Left {
+        return;
+    }
+    if model.inspect_mode {
+        model.inspect_frozen = !model.inspect_frozen;
+    } else {
+        model.push_position = Some(app.mouse.position());
+    }
+}
+
+fn mouse_moved(app: &App, model: &mut Model, position: Point2) {
+    model.cursor = Some(position);
+    if let Some(push_position) = &mut model.push_position {
+        *push_position = position;
+    }
+    if let Some(pan_anchor) = &mut model.pan_anchor {
+        let viewport = &mut model.viewport;
+        viewport.pan += (position - *pan_anchor) / (app.window_rect().wh() * viewport.zoom);
+        *pan_anchor = position;
+    }
+}
+
+fn mouse_released(_app: &App, model: &mut Model, button: MouseButton) {
+    match button {
+        MouseButton::Left => model.push_position = None,
+        MouseButton::Right => model.pan_anchor = None,
+        _ => (),
     }
 }
 
+/// Zooms the field in or out around the cursor.
+fn mouse_wheel(app: &App, model: &mut Model, delta: MouseScrollDelta, _phase: TouchPhase) {
+    if model.egui.ctx().is_pointer_over_area() {
+        return;
+    }
+    let lines = match delta {
+        MouseScrollDelta::LineDelta(_, y) => y,
+        MouseScrollDelta::PixelDelta(position) => position.y as f32 / 100.0,
+    };
+    let viewport = &mut model.viewport;
+    let zoom = (viewport.zoom * ZOOM_STEP.powf(lines)).clamp(ZOOM_MIN, ZOOM_MAX);
+    // The field under the cursor stays in place.
+    let win = app.window_rect();
+    let offset = app.mouse.position() - win.xy();
+    viewport.pan += (offset / zoom - offset / viewport.zoom) / win.wh();
+    viewport.zoom = zoom;
+}
+
+/// Spawns a burst of particles at the cursor into the selected particle system, takes a
+/// screenshot or toggles fullscreen.
+fn key_pressed(app: &App, model: &mut Model, key: Key) {
+    if model.egui.ctx().wants_keyboard_input() {
+        return;
+    }
+    match key {
+        BURST_KEY => model.particle_systems[model.selected_particle_system]
+            .spawn_at(app.mouse.position(), model.burst_count),
+        SCREENSHOT_KEY => model.screenshot_requested = true,
+        FULLSCREEN_KEY => {
+            let window = app.main_window();
+            window.set_fullscreen(!window.is_fullscreen());
+        }
+        _ => (),
+    }
+}
+
+/// Restores the field and particle parameters to their defaults, keeping the noise seed.
+fn reset_to_defaults(app: &App, model: &mut Model) {
+    if let Some(z) = model.frozen_z.take() {
+        model.reference_time = resumed_reference_time(app, model.running, model.speed, z);
+    }
+    if model.running != RUNNING_DEFAULT {
+        model.reference_time = app.time * model.speed - model.reference_time;
+        model.running = RUNNING_DEFAULT;
+    }
+    model.speed = SPEED_DEFAULT;
+    model.depth_speed = DEPTH_SPEED_DEFAULT;
+    model.step_sample = STEP_DEFAULT;
+    model.substeps = SUBSTEPS_DEFAULT;
+    model.grid_mode = GridMode::Pixels;
+    model.max_angle = MAX_ANGLE_DEFAULT;
+    model.animate_max_angle = false;
+    model.frequency = FREQUENCY_DEFAULT;
+    model.colormap = Colormap::Gray;
+    model.value_mode = ValueMode::Angle;
+    model.show_arrows = SHOW_ARROWS_DEFAULT;
+    model.arrow_heads = true;
+    model.arrow_anchor = ArrowAnchor::Center;
+    model.vector_mode = VectorMode::Angle;
+    model.arrows_on_top = false;
+    model.stroke_weight = STROKE_WEIGHT_DEFAULT;
+    model.show_values = SHOW_VALUES_DEFAULT;
+    for particle_system in &mut model.particle_systems {
+        particle_system.reset_config();
+    }
+}
+
+/// Color picker button editing `color` in place.
+fn color_edit_button(ui: &mut egui::Ui, color: &mut rgb::Srgb<u8>) {
+    let mut srgb = [color.red, color.green, color.blue];
+    ui.color_edit_button_srgb(&mut srgb);
+    *color = rgb::Srgb::new(srgb[0], srgb[1], srgb[2]);
+}
+
 fn update(app: &App, model: &mut Model, update: Update) {
     let noise_z = noise_z(app, model) as f32;
+    let inspected: Option<(ParticleInfo, Radian)> = if model.inspect_frozen {
+        model
+            .particle_systems
+            .iter()
+            .filter_map(|particle_system| particle_system.nearest(app.mouse.position()))
+            .min_by(|a, b| {
+                let dist_a = app.mouse.position().distance_squared(a.position);
+                let dist_b = app.mouse.position().distance_squared(b.position);
+                dist_a.total_cmp(&dist_b)
+            })
+            .map(|info| {
+                let params = field_params(model, noise_z);
+                let angle = field_angle(model, app.window_rect(), info.position, &params);
+                (info, angle)
+            })
+    } else {
+        None
+    };
+    let cursor_derivatives = model
+        .cursor
+        .filter(|&cursor| app.window_rect().contains(cursor))
+        .map(|cursor| {
+            let params = field_params(model, noise_z);
+            divergence_curl(model, app.window_rect(), cursor, &params)
+        });
 
+    // The capture requested during the previous update has been rendered by now.
+    model.capture_requested = false;
+    if let Some((path, region)) = model.pending_crop.take() {
+        crop_capture(app, &path, region);
+    }
+    if model.frame_times.len() == FRAME_TIME_SAMPLES {
+        model.frame_times.pop_front();
+    }
+    model.frame_times.push_back(update.since_last.as_secs_f32());
+    let frame_time = model.frame_times.iter().sum::<f32>() / model.frame_times.len() as f32;
+    if app.time - model.last_log_time >= LOG_INTERVAL {
+        model.last_log_time = app.time;
+        let count: usize = model
+            .particle_systems
+            .iter()
+            .map(|system| system.count())
+            .sum();
+        debug!(
+            "{} particles, {:.1} ms per frame",
+            count,
+            frame_time * 1000.0
+        );
+    }
+    let previous_noise = (model.noise_source, model.seed, model.seed_b);
+    let previous_field_kind = model.field_kind;
+    let previous_backend = model.particle_backend;
+    let mut export_rust = false;
+    let mut export_svg_requested = false;
+    let mut save_requested = false;
+    let mut load_requested = false;
+    let mut add_system_requested = false;
+    let mut remove_system_requested = false;
+    let mut selected_preset = None;
+    let mut save_preset_requested = false;
+    let mut load_particles_requested = false;
+    let mut load_mask_requested = false;
+    let mut clear_mask_requested = false;
+    let previous_mask_version = model.mask_version;
+    let previous_mask_threshold = model.mask.as_ref().map(|mask| mask.threshold);
+    let mut reset_requested = false;
+    let mut rebuild_particle_texture_requested = false;
     let egui = &mut model.egui;
     egui.set_elapsed_time(update.since_start);
     let ctx = egui.begin_frame();
-    egui::Window::new("Settings").show(&ctx, |ui| {
+    let settings_window = egui::Window::new("Settings").show(&ctx, |ui| {
         ui.vertical(|ui| {
+            ui.label(format!(
+                "{:.1} ms / {:.0} fps",
+                frame_time * 1000.0,
+                1.0 / frame_time
+            ));
+            if let Some((divergence, curl)) = cursor_derivatives {
+                ui.label(format!(
+                    "cursor divergence: {:.3}, curl: {:.3}",
+                    divergence, curl
+                ));
+            }
             ui.heading("Noise control");
-            ui.add(egui::Slider::new(&mut model.step_sample, 1..=100).text("Steps"));
+            egui::ComboBox::from_id_source("Field Kind Selection")
+                .selected_text(format!("{:?}", model.field_kind))
+                .show_ui(ui, |ui| {
+                    ui.selectable_value(&mut model.field_kind, FieldKind::Noise, "Noise");
+                    ui.selectable_value(&mut model.field_kind, FieldKind::Expression, "Expression");
+                });
+            if model.field_kind == FieldKind::Expression {
+                if ui.text_edit_singleline(&mut model.expression).changed() {
+                    match ExprField::parse(&model.expression) {
+                        Ok(field) => {
+                            model.expression_field = field;
+                            model.expression_error = None;
+                            model.noise_version += 1;
+                        }
+                        Err(error) => model.expression_error = Some(error.to_string()),
+                    }
+                }
+                if let Some(error) = &model.expression_error {
+                    ui.colored_label(egui::Color32::RED, error);
+                }
+            }
+            egui::ComboBox::from_id_source("Noise Source Selection")
+                .selected_text(format!("{:?}", model.noise_source))
+                .show_ui(ui, |ui| {
+                    ui.selectable_value(&mut model.noise_source, NoiseSource::Perlin, "Perlin");
+                    ui.selectable_value(&mut model.noise_source, NoiseSource::Simplex, "Simplex");
+                    ui.selectable_value(&mut model.noise_source, NoiseSource::Value, "Value");
+                    ui.selectable_value(
+                        &mut model.noise_source,
+                        NoiseSource::Worley(WorleyDistance::Euclidean),
+                        "Worley",
+                    );
+                });
+            if let NoiseSource::Worley(distance) = &mut model.noise_source {
+                egui::ComboBox::from_id_source("Worley Distance Selection")
+                    .selected_text(format!("{:?} distance", distance))
+                    .show_ui(ui, |ui| {
+                        for option in [
+                            WorleyDistance::Euclidean,
+                            WorleyDistance::Manhattan,
+                            WorleyDistance::Chebyshev,
+                        ] {
+                            ui.selectable_value(distance, option, format!("{:?}", option));
+                        }
+                    });
+            }
+            ui.horizontal(|ui| {
+                ui.add(egui::DragValue::new(&mut model.seed));
+                ui.label("seed");
+                if ui.button("Randomize seed").clicked() {
+                    model.seed = random();
+                }
+            });
+            ui.horizontal(|ui| {
+                ui.add(egui::DragValue::new(&mut model.seed_b));
+                ui.label("seed B");
+                ui.add(egui::Slider::new(&mut model.blend, 0.0..=1.0).text("blend"));
+            });
+            ui.horizontal(|ui| {
+                ui.add(egui::Slider::new(&mut model.step_sample, 1..=100).text("Steps"));
+                egui::ComboBox::from_id_source("Grid Mode Selection")
+                    .selected_text(format!("{:?}", model.grid_mode))
+                    .show_ui(ui, |ui| {
+                        ui.selectable_value(&mut model.grid_mode, GridMode::Pixels, "Pixels");
+                        ui.selectable_value(&mut model.grid_mode, GridMode::Columns, "Columns");
+                    });
+            });
             ui.add(
                 egui::Slider::new(&mut model.max_angle, 0.0..=2.0 * PI)
                     .text("Max angle")
                     .suffix("rad"),
             );
+            ui.horizontal(|ui| {
+                ui.checkbox(&mut model.animate_max_angle, "Animate max angle");
+                ui.add_enabled_ui(model.animate_max_angle, |ui| {
+                    ui.add(
+                        egui::Slider::new(&mut model.max_angle_amplitude, 0.0..=2.0 * PI)
+                            .text("amplitude"),
+                    );
+                    ui.add(
+                        egui::Slider::new(&mut model.max_angle_period, 0.5..=60.0)
+                            .text("period")
+                            .suffix("s"),
+                    );
+                });
+            });
             ui.add(
                 egui::Slider::new(&mut model.frequency, 0.1..=100.0)
                     .text("Frequency")
                     .logarithmic(true),
             );
+            ui.add(egui::Slider::new(&mut model.fbm.octaves, 1..=8).text("Octaves"));
+            ui.add(egui::Slider::new(&mut model.fbm.lacunarity, 1.0..=4.0).text("Lacunarity"));
+            ui.add(egui::Slider::new(&mut model.fbm.persistence, 0.0..=1.0).text("Persistence"));
+            ui.checkbox(&mut model.fbm.seamless, "Seamless tiling");
+            ui.add(egui::Slider::new(&mut model.warp_strength, 0.0..=1.0).text("Warp"));
+            egui::ComboBox::from_id_source("Noise Dimensions Selection")
+                .selected_text(match model.noise_dimensions {
+                    NoiseDimensions::Two => "2D, still",
+                    NoiseDimensions::Four => "4D, animated",
+                })
+                .show_ui(ui, |ui| {
+                    ui.selectable_value(
+                        &mut model.noise_dimensions,
+                        NoiseDimensions::Two,
+                        "2D, still",
+                    );
+                    ui.selectable_value(
+                        &mut model.noise_dimensions,
+                        NoiseDimensions::Four,
+                        "4D, animated",
+                    );
+                });
+            egui::ComboBox::from_id_source("Domain Selection")
+                .selected_text(format!("{:?} domain", model.domain))
+                .show_ui(ui, |ui| {
+                    ui.selectable_value(&mut model.domain, Domain::Cartesian, "Cartesian");
+                    ui.selectable_value(&mut model.domain, Domain::Polar, "Polar");
+                });
             ui.horizontal(|ui| {
-                egui::ComboBox::from_id_source("Angle Color Selection")
-                    .selected_text(format!("{:?}", model.angle_color))
-                    .show_ui(ui, |ui| {
-                        ui.selectable_value(&mut model.angle_color, AngleColor::Gray, "Gray");
-                        ui.selectable_value(&mut model.angle_color, AngleColor::HSV, "Hue");
-                    });
-                ui.checkbox(&mut model.show_values, "Show Values");
-                ui.checkbox(&mut model.show_arrows, "Show Arrows");
+                ui.checkbox(&mut model.orientation.flip_x, "Flip x")
+                    .on_hover_text("Field x grows toward the right instead of the left");
+                ui.checkbox(&mut model.orientation.flip_y, "Flip y")
+                    .on_hover_text("Field y grows toward the top instead of the bottom");
+            });
+            ui.horizontal(|ui| {
+                ui.add(
+                    egui::Slider::new(&mut model.viewport.zoom, ZOOM_MIN..=ZOOM_MAX)
+                        .logarithmic(true)
+                        .text("zoom"),
+                )
+                .on_hover_text("Scroll to zoom, drag with the right button to pan");
+                if ui.button("Reset view").clicked() {
+                    model.viewport = Viewport::default();
+                }
             });
-            ui.separator();
-            ui.heading("Update vector field");
-            ui.add(
-                egui::Slider::new(&mut model.speed, 0.0..=100.0)
-                    .text("Speed")
-                    .logarithmic(true),
-            );
             if ui
-                .button(if model.running { "Pause" } else { "Run" })
-                .clicked()
+                .checkbox(&mut model.cache_field, "Cache field grid")
+                .changed()
             {
-                model.reference_time = app.time * model.speed - model.reference_time;
-                model.running = !model.running;
+                model.field_grid = None;
+            }
+            if ui
+                .checkbox(&mut model.normalize_noise, "Normalize to observed range")
+                .changed()
+            {
+                model.noise_range = None;
             }
-            ui.separator();
-            ui.heading("Particles");
-            ui.add(egui::Slider::new(&mut model.particle_draw_speed, 1..=100).text("Draw speed"));
             ui.horizontal(|ui| {
-                if ui.button("Reset particles").clicked() {
-                    model.particle_system.reset();
-                    model.particle_texture = wgpu::TextureBuilder::new()
-                        .size([
-                            app.main_window().rect().w() as u32,
-                            app.main_window().rect().h() as u32,
-                        ])
-                        .usage(
-                            wgpu::TextureUsages::RENDER_ATTACHMENT
-                                | wgpu::TextureUsages::TEXTURE_BINDING,
-                        )
-                        .sample_count(1) //.sample_count(window.msaa_samples())
-                        .format(wgpu::TextureFormat::Rgba16Float)
-                        .build(app.main_window().device());
+                let name = match model.remap_curve {
+                    RemapCurve::Linear => "Linear",
+                    RemapCurve::Smoothstep => "Smoothstep",
+                    RemapCurve::Power(_) => "Power",
+                };
+                egui::ComboBox::from_id_source("Remap Curve Selection")
+                    .selected_text(name)
+                    .show_ui(ui, |ui| {
+                        ui.selectable_value(&mut model.remap_curve, RemapCurve::Linear, "Linear");
+                        ui.selectable_value(
+                            &mut model.remap_curve,
+                            RemapCurve::Smoothstep,
+                            "Smoothstep",
+                        );
+                        if ui.selectable_label(name == "Power", "Power").clicked()
+                            && name != "Power"
+                        {
+                            model.remap_curve = RemapCurve::Power(REMAP_EXPONENT_DEFAULT);
+                        }
+                    });
+                ui.label("Remap");
+                if let RemapCurve::Power(exponent) = &mut model.remap_curve {
+                    ui.add(
+                        egui::Slider::new(exponent, 0.1..=10.0)
+                            .text("exponent")
+                            .logarithmic(true),
+                    );
                 }
-                ui.checkbox(&mut model.enable_particles, "Enable particles");
             });
-            model.particle_system.config_gui(ui);
-        });
-    });
-
-    if model.enable_particles {
-        let draw = app.draw();
-        for _ in 0..model.particle_draw_speed {
-            model
-                .particle_system
-                .update(noise_z, model.frequency, model.max_angle);
-            model.particle_system.draw(&draw);
-        }
-        let window = app.main_window();
-        let device = window.device();
-        let ce_desc = wgpu::CommandEncoderDescriptor {
-            label: Some("texture renderer"),
-        };
-        let mut encoder = device.create_command_encoder(&ce_desc);
+            ui.horizontal(|ui| {
+                egui::ComboBox::from_id_source("Value Mode Selection")
+                    .selected_text(format!("{:?}", model.value_mode))
+                    .show_ui(ui, |ui| {
+                        ui.selectable_value(&mut model.value_mode, ValueMode::Angle, "Angle");
+                        ui.selectable_value(
+                            &mut model.value_mode,
+                            ValueMode::Magnitude,
+                            "Magnitude",
+                        );
+                    });
+                egui::ComboBox::from_id_source("Colormap Selection")
+                    .selected_text(format!("{:?}", model.colormap))
+                    .show_ui(ui, |ui| {
+                        for colormap in Colormap::ALL {
+                            ui.selectable_value(
+                                &mut model.colormap,
+                                colormap,
+                                format!("{:?}", colormap),
+                            );
+                        }
+                    });
+                ui.checkbox(&mut model.show_values, "Show Values");
+                ui.checkbox(&mut model.show_arrows, "Show Arrows");
+            });
+            ui.add_enabled_ui(model.show_arrows, |ui| {
+                ui.horizontal(|ui| {
+                    ui.checkbox(&mut model.arrow_heads, "Arrow heads");
+                    egui::ComboBox::from_id_source("Arrow Anchor Selection")
+                        .selected_text(format!("{:?} anchor", model.arrow_anchor))
+                        .show_ui(ui, |ui| {
+                            ui.selectable_value(
+                                &mut model.arrow_anchor,
+                                ArrowAnchor::Center,
+                                "Center",
+                            );
+                            ui.selectable_value(&mut model.arrow_anchor, ArrowAnchor::Base, "Base");
+                        });
+                    ui.add(
+                        egui::Slider::new(&mut model.stroke_weight, 0.5..=8.0)
+                            .text("Stroke weight"),
+                    );
+                });
+                ui.horizontal(|ui| {
+                    egui::ComboBox::from_id_source("Vector Mode Selection")
+                        .selected_text(format!("{:?} vectors", model.vector_mode))
+                        .show_ui(ui, |ui| {
+                            ui.selectable_value(&mut model.vector_mode, VectorMode::Angle, "Angle");
+                            ui.selectable_value(
+                                &mut model.vector_mode,
+                                VectorMode::Gradient,
+                                "Gradient",
+                            );
+                        });
+                    ui.add_enabled(
+                        model.vector_mode == VectorMode::Angle,
+                        egui::Checkbox::new(&mut model.scale_by_magnitude, "Scale by magnitude"),
+                    );
+                    ui.checkbox(&mut model.arrows_on_top, "Over particles");
+                });
+            });
+            ui.horizontal(|ui| {
+                ui.add(
+                    egui::Slider::new(&mut model.magnitude_threshold, 0.0..=MAGNITUDE_MAX)
+                        .text("Hide below magnitude"),
+                );
+                ui.checkbox(&mut model.hide_flat_cells, "cells too");
+            });
+            ui.horizontal(|ui| {
+                load_mask_requested = ui.button("Load mask").clicked();
+                clear_mask_requested = ui
+                    .add_enabled(model.mask.is_some(), egui::Button::new("Clear"))
+                    .clicked();
+                match &mut model.mask {
+                    Some(mask) => {
+                        ui.add(egui::Slider::new(&mut mask.threshold, 0..=255).text("threshold"));
+                    }
+                    None => {
+                        ui.label(MASK_PATH);
+                    }
+                }
+            });
+            ui.checkbox(&mut model.show_critical_points, "Show critical points");
+            ui.horizontal(|ui| {
+                ui.checkbox(&mut model.show_contours, "Show contours");
+                ui.add(egui::Slider::new(&mut model.contour_count, 1..=50).text("levels"));
+                color_edit_button(ui, &mut model.contour_color);
+            });
+            ui.horizontal(|ui| {
+                color_edit_button(ui, &mut model.background_color);
+                ui.label("background");
+                color_edit_button(ui, &mut model.arrow_color);
+                ui.label("arrows");
+            });
+            ui.horizontal(|ui| {
+                ui.checkbox(&mut model.show_streamlines, "Show streamlines");
+                ui.add(egui::DragValue::new(&mut model.streamline_steps).clamp_range(1..=500));
+                ui.label("steps");
+            });
+            ui.add(
+                egui::Slider::new(&mut model.palette_cycle_speed, 0.0..=2.0)
+                    .text("Palette cycle")
+                    .suffix("turn/s"),
+            );
+            ui.horizontal(|ui| {
+                ui.checkbox(&mut model.value_grid_texture, "Value grid via texture");
+                egui::ComboBox::from_id_source("Value Grid Filter Selection")
+                    .selected_text(format!("{:?}", model.value_grid_filter))
+                    .show_ui(ui, |ui| {
+                        ui.selectable_value(
+                            &mut model.value_grid_filter,
+                            ValueGridFilter::Nearest,
+                            "Nearest",
+                        );
+                        ui.selectable_value(
+                            &mut model.value_grid_filter,
+                            ValueGridFilter::Bilinear,
+                            "Bilinear",
+                        );
+                    });
+            });
+            ui.separator();
+            ui.heading("Update vector field");
+            ui.add(
+                egui::Slider::new(&mut model.speed, 0.0..=100.0)
+                    .text("Time speed")
+                    .logarithmic(true),
+            );
+            ui.add(
+                egui::Slider::new(&mut model.depth, 0.0..=DEPTH_MAX)
+                    .text("Depth")
+                    .clamp_to_range(false),
+            );
+            ui.add(egui::Slider::new(&mut model.depth_speed, 0.0..=1.0).text("Depth speed"));
+            if ui
+                .button(if model.running { "Pause" } else { "Run" })
+                .clicked()
+            {
+                model.reference_time = app.time * model.speed - model.reference_time;
+                model.running = !model.running;
+            }
+            let mut frozen = model.frozen_z.is_some();
+            if ui.checkbox(&mut frozen, "Freeze field").changed() {
+                model.frozen_z = match model.frozen_z {
+                    Some(z) => {
+                        model.reference_time =
+                            resumed_reference_time(app, model.running, model.speed, z);
+                        None
+                    }
+                    None => Some(noise_z),
+                };
+            }
+            if !model.running && model.frozen_z.is_none() {
+                // Paused, `reference_time` is the time coordinate of the noise.
+                ui.add(
+                    egui::Slider::new(&mut model.reference_time, 0.0..=TIME_SCRUB_MAX)
+                        .text("Time")
+                        .clamp_to_range(false),
+                );
+            }
+            ui.horizontal(|ui| {
+                if ui
+                    .checkbox(&mut model.screensaver.enabled, "Screensaver")
+                    .changed()
+                {
+                    model.screensaver.switch_time = None;
+                }
+                ui.add(
+                    egui::DragValue::new(&mut model.screensaver.interval)
+                        .clamp_range(1.0..=3600.0)
+                        .suffix("s"),
+                );
+                ui.label("interval");
+                ui.add(
+                    egui::DragValue::new(&mut model.screensaver.fade)
+                        .clamp_range(0.0..=600.0)
+                        .suffix("s"),
+                );
+                ui.label("fade");
+            });
+            ui.horizontal(|ui| {
+                ui.label("Seeds");
+                if ui
+                    .text_edit_singleline(&mut model.screensaver.seeds)
+                    .changed()
+                {
+                    model.screensaver.order = shuffled_seeds(&model.screensaver.seeds);
+                    model.screensaver.next_index = 0;
+                }
+            });
+            ui.separator();
+            let count: usize = model
+                .particle_systems
+                .iter()
+                .map(|system| system.count())
+                .sum();
+            ui.heading(format!("Particles ({})", count));
+            ui.add(egui::Slider::new(&mut model.particle_draw_speed, 1..=100).text("Draw speed"));
+            ui.add(egui::Slider::new(&mut model.substeps, 1..=16).text("Substeps"));
+            ui.horizontal(|ui| {
+                let previous_format = model.particle_texture_format;
+                egui::ComboBox::from_id_source("Particle Texture Format")
+                    .selected_text(format!("{:?}", model.particle_texture_format))
+                    .show_ui(ui, |ui| {
+                        ui.selectable_value(
+                            &mut model.particle_texture_format,
+                            ParticleTextureFormat::Float16,
+                            "Float16",
+                        );
+                        ui.selectable_value(
+                            &mut model.particle_texture_format,
+                            ParticleTextureFormat::Unorm8,
+                            "Unorm8",
+                        );
+                    });
+                ui.label("Texture format");
+                let previous_sample_count = model.particle_sample_count;
+                egui::ComboBox::from_id_source("Particle Sample Count")
+                    .selected_text(format!("{}x", model.particle_sample_count))
+                    .show_ui(ui, |ui| {
+                        for sample_count in PARTICLE_SAMPLE_COUNTS {
+                            ui.selectable_value(
+                                &mut model.particle_sample_count,
+                                sample_count,
+                                format!("{}x", sample_count),
+                            );
+                        }
+                    });
+                ui.label("MSAA")
+                    .on_hover_text("Smoother particle edges, at a memory cost");
+                if model.particle_texture_format != previous_format
+                    || model.particle_sample_count != previous_sample_count
+                {
+                    rebuild_particle_texture_requested = true;
+                    info!(
+                        "Rebuilding the particle texture as {:?}, {}x MSAA",
+                        model.particle_texture_format, model.particle_sample_count
+                    );
+                }
+            });
+            ui.horizontal(|ui| {
+                ui.add_enabled(
+                    !model.persistent_canvas,
+                    egui::Checkbox::new(&mut model.trails, "Trails"),
+                );
+                ui.add_enabled(
+                    model.trails && !model.persistent_canvas,
+                    egui::Slider::new(&mut model.trail_alpha, 0.02..=0.2).text("fade"),
+                );
+            });
+            ui.horizontal(|ui| {
+                ui.checkbox(&mut model.persistent_canvas, "Persistent canvas")
+                    .on_hover_text("Accumulates the particles without ever fading them");
+                if ui.button("Clear canvas").clicked() {
+                    rebuild_particle_texture_requested = true;
+                }
+            });
+            ui.horizontal(|ui| {
+                if ui.button("Reset particles").clicked() {
+                    model.reset_snapshot = Some(
+                        model
+                            .particle_systems
+                            .iter()
+                            .map(|particle_system| particle_system.snapshot())
+                            .collect(),
+                    );
+                    for particle_system in &mut model.particle_systems {
+                        particle_system.reset();
+                    }
+                    info!("Reset {} particle systems", model.particle_systems.len());
+                    rebuild_particle_texture_requested = true;
+                }
+                if ui
+                    .add_enabled(
+                        model.reset_snapshot.is_some(),
+                        egui::Button::new("Undo reset"),
+                    )
+                    .clicked()
+                {
+                    let snapshot = model.reset_snapshot.take().unwrap_or_default();
+                    for (particle_system, particles) in
+                        model.particle_systems.iter_mut().zip(snapshot)
+                    {
+                        // Empty when the particles could not be read back.
+                        if !particles.is_empty() {
+                            particle_system.load_positions(particles);
+                        }
+                    }
+                }
+                ui.checkbox(&mut model.enable_particles, "Enable particles");
+            });
+            ui.horizontal(|ui| {
+                load_particles_requested = ui.button("Load particles").clicked();
+                ui.label(PARTICLES_PATH);
+            });
+            ui.checkbox(&mut model.show_density, "Show density");
+            ui.horizontal(|ui| {
+                ui.label("Drag to push");
+                ui.add(egui::Slider::new(&mut model.push_radius, 10.0..=400.0).text("radius"));
+                ui.add(egui::Slider::new(&mut model.push_strength, 0.0..=2000.0).text("strength"));
+            });
+            ui.horizontal(|ui| {
+                ui.label(format!("Press {:?} to spawn", BURST_KEY));
+                ui.add(egui::DragValue::new(&mut model.burst_count).clamp_range(0..=10_000));
+                ui.label("particles at the cursor");
+            });
+            if ui
+                .checkbox(&mut model.inspect_mode, "Inspect on click")
+                .changed()
+                && !model.inspect_mode
+            {
+                model.inspect_frozen = false;
+            }
+            ui.horizontal(|ui| {
+                egui::ComboBox::from_id_source("Particle Backend")
+                    .selected_text(format!("{:?}", model.particle_backend))
+                    .show_ui(ui, |ui| {
+                        ui.selectable_value(
+                            &mut model.particle_backend,
+                            ParticleBackend::Cpu,
+                            "CPU",
+                        );
+                        ui.selectable_value(
+                            &mut model.particle_backend,
+                            ParticleBackend::Gpu,
+                            "GPU",
+                        );
+                    });
+                ui.label("backend");
+            });
+            ui.horizontal(|ui| {
+                for index in 0..model.particle_systems.len() {
+                    ui.selectable_value(
+                        &mut model.selected_particle_system,
+                        index,
+                        format!("System {}", index + 1),
+                    );
+                }
+                add_system_requested = ui.button("Add").clicked();
+                remove_system_requested = ui
+                    .add_enabled(
+                        model.particle_systems.len() > 1,
+                        egui::Button::new("Remove"),
+                    )
+                    .clicked();
+            });
+            model.particle_systems[model.selected_particle_system].config_gui(ui);
+            ui.separator();
+            ui.heading("Output");
+            ui.horizontal(|ui| {
+                ui.checkbox(&mut model.fixed_resolution, "Fixed resolution");
+                ui.add(
+                    egui::DragValue::new(&mut model.internal_resolution[0])
+                        .clamp_range(1..=8192)
+                        .suffix("px"),
+                );
+                ui.label("x");
+                ui.add(
+                    egui::DragValue::new(&mut model.internal_resolution[1])
+                        .clamp_range(1..=8192)
+                        .suffix("px"),
+                );
+                egui::ComboBox::from_id_source("Resolution Fit Selection")
+                    .selected_text(format!("{:?}", model.resolution_fit))
+                    .show_ui(ui, |ui| {
+                        ui.selectable_value(&mut model.resolution_fit, ResolutionFit::Fit, "Fit");
+                        ui.selectable_value(&mut model.resolution_fit, ResolutionFit::Fill, "Fill");
+                    });
+            });
+            ui.horizontal(|ui| {
+                let window = app.main_window();
+                let mut fullscreen = window.is_fullscreen();
+                if ui
+                    .checkbox(
+                        &mut fullscreen,
+                        format!("Fullscreen ({:?})", FULLSCREEN_KEY),
+                    )
+                    .changed()
+                {
+                    window.set_fullscreen(fullscreen);
+                }
+                ui.add(egui::DragValue::new(&mut model.window_size[0]).prefix("w: "));
+                ui.add(egui::DragValue::new(&mut model.window_size[1]).prefix("h: "));
+                if ui
+                    .add_enabled(!fullscreen, egui::Button::new("Resize window"))
+                    .clicked()
+                {
+                    let [width, height] = model.window_size;
+                    window.set_inner_size_points(width as f32, height as f32);
+                }
+            });
+            ui.horizontal(|ui| {
+                save_requested = ui.button("Save settings").clicked();
+                load_requested = ui.button("Load settings").clicked();
+                ui.checkbox(&mut model.hot_reload, "Hot reload settings.toml");
+            });
+            ui.horizontal(|ui| {
+                egui::ComboBox::from_id_source("Preset Selection")
+                    .selected_text(model.preset.as_deref().unwrap_or("Preset"))
+                    .show_ui(ui, |ui| {
+                        for name in &model.presets {
+                            let selected = model.preset.as_ref() == Some(name);
+                            if ui.selectable_label(selected, name).clicked() {
+                                selected_preset = Some(name.clone());
+                            }
+                        }
+                    });
+                if ui.button("Refresh").clicked() {
+                    model.presets = list_presets();
+                }
+            });
+            ui.horizontal(|ui| {
+                ui.text_edit_singleline(&mut model.preset_name);
+                save_preset_requested = ui.button("Save current as preset…").clicked();
+            });
+            reset_requested = ui.button("Reset all to defaults").clicked();
+            ui.heading("Export");
+            ui.horizontal(|ui| {
+                if ui.button("Save PNG").clicked() {
+                    model.capture_requested = true;
+                }
+                ui.label(format!("{:?} for a numbered shot", SCREENSHOT_KEY));
+                if ui
+                    .button(if model.recording { "Stop" } else { "Record" })
+                    .clicked()
+                {
+                    model.recording = !model.recording;
+                    model.frame_index = 0;
+                }
+                if model.recording {
+                    ui.label(format!("{} frames", model.frame_index));
+                }
+                if ui.button("Export frame").clicked() {
+                    model.export_requested = true;
+                }
+                ui.add(egui::Slider::new(&mut model.export_subframes, 1..=16).text("Sub-frames"));
+            });
+            ui.horizontal(|ui| {
+                export_svg_requested = ui.button("Export SVG").clicked();
+                export_rust = ui.button("Export as Rust").clicked();
+            });
+            ui.horizontal(|ui| {
+                egui::ComboBox::from_id_source("Crop Mode Selection")
+                    .selected_text(format!("{:?}", model.crop_mode))
+                    .show_ui(ui, |ui| {
+                        ui.selectable_value(&mut model.crop_mode, CropMode::Full, "Full");
+                        ui.selectable_value(
+                            &mut model.crop_mode,
+                            CropMode::ExcludeGui,
+                            "Exclude GUI",
+                        );
+                        ui.selectable_value(&mut model.crop_mode, CropMode::Region, "Region");
+                    });
+                ui.label("PNG crop");
+            });
+            if model.crop_mode == CropMode::Region {
+                ui.horizontal(|ui| {
+                    let [x, y, width, height] = &mut model.crop_region;
+                    ui.add(egui::DragValue::new(x).prefix("x: "));
+                    ui.add(egui::DragValue::new(y).prefix("y: "));
+                    ui.add(egui::DragValue::new(width).prefix("w: "));
+                    ui.add(egui::DragValue::new(height).prefix("h: "));
+                });
+            }
+        });
+    });
+    model.gui_rect = settings_window.map(|window| window.response.rect);
+    let mut close_snippet = false;
+    if let Some(snippet) = &model.rust_snippet {
+        egui::Window::new("Rust snippet").show(&ctx, |ui| {
+            ui.monospace(snippet);
+            close_snippet = ui.button("Close").clicked();
+        });
+    }
+    if close_snippet {
+        model.rust_snippet = None;
+    }
+    if let Some((info, angle)) = inspected {
+        egui::show_tooltip_at_pointer(&ctx, egui::Id::new("Particle inspector"), |ui| {
+            ui.label(format!(
+                "position: ({:.1}, {:.1})",
+                info.position.x, info.position.y
+            ));
+            ui.label(format!(
+                "velocity: ({:.1}, {:.1}), |{:.1}| px/s",
+                info.velocity.x,
+                info.velocity.y,
+                info.velocity.length()
+            ));
+            ui.label(format!("age: {:.1} s", info.age));
+            ui.label(format!(
+                "field angle: {:.3} rad ({:.1}°)",
+                angle,
+                angle.to_degrees()
+            ));
+        });
+    }
+    drop(ctx);
+
+    if (model.noise_source, model.seed, model.seed_b) != previous_noise {
+        set_noise(model, model.noise_source.build(model.seed));
+        model.noise_b = model.noise_source.build(model.seed_b);
+        for (index, particle_system) in model.particle_systems.iter_mut().enumerate() {
+            particle_system.set_seed(model.seed as u64 + index as u64);
+        }
+    }
+    if model.field_kind != previous_field_kind {
+        model.noise_version += 1;
+    }
+
+    if model.particle_backend != previous_backend {
+        let window = app.main_window();
+        let version = field_version(model);
+        for (index, particle_system) in model.particle_systems.iter_mut().enumerate() {
+            let config = particle_system.config();
+            *particle_system = build_particle_system(
+                model.particle_backend,
+                window.rect(),
+                model.seed as u64 + index as u64,
+                window.device_queue_pair(),
+            );
+            particle_system.apply_config(&config);
+            particle_system.set_mask(model.mask.clone());
+            particle_system.set_field_version(version);
+        }
+    }
+    if add_system_requested || remove_system_requested {
+        // The snapshot no longer matches the systems.
+        model.reset_snapshot = None;
+    }
+    if add_system_requested {
+        let window = app.main_window();
+        // Seeded apart from the others, not to spawn its particles over theirs.
+        let mut particle_system = build_particle_system(
+            model.particle_backend,
+            window.rect(),
+            model.seed as u64 + model.particle_systems.len() as u64,
+            window.device_queue_pair(),
+        );
+        particle_system.set_mask(model.mask.clone());
+        particle_system.set_field_version(field_version(model));
+        model.particle_systems.push(particle_system);
+        model.selected_particle_system = model.particle_systems.len() - 1;
+    }
+    if remove_system_requested && model.particle_systems.len() > 1 {
         model
-            .renderer
-            .render_to_texture(device, &mut encoder, &draw, &model.particle_texture);
+            .particle_systems
+            .remove(model.selected_particle_system);
+        model.selected_particle_system = model
+            .selected_particle_system
+            .min(model.particle_systems.len() - 1);
+    }
+
+    if export_svg_requested {
+        export_svg(app, model, noise_z);
+    }
+    if export_rust {
+        model.rust_snippet = Some(rust_snippet(model));
+    }
+
+    if save_requested {
+        save_settings(model, Path::new(SETTINGS_PATH));
+    }
+    if load_requested {
+        load_settings(model, Path::new(SETTINGS_PATH));
+    }
+    if let Some(name) = selected_preset {
+        load_settings(model, &preset_path(&name));
+        model.preset = Some(name);
+    }
+    if save_preset_requested {
+        save_preset(model);
+    }
+    if load_particles_requested {
+        load_particles(app, model);
+    }
+    if load_mask_requested {
+        load_mask(model);
+    }
+    if clear_mask_requested {
+        model.mask = None;
+        model.mask_version += 1;
+    }
+    if model.mask.as_ref().map(|mask| mask.threshold) != previous_mask_threshold {
+        model.mask_version += 1;
+    }
+    if model.mask_version != previous_mask_version {
+        for particle_system in &mut model.particle_systems {
+            particle_system.set_mask(model.mask.clone());
+        }
+    }
+    if reset_requested {
+        reset_to_defaults(app, model);
+    }
+    if rebuild_particle_texture_requested {
+        let size = model.particle_texture.size();
+        rebuild_particle_texture(app, model, size);
+    }
+
+    // Drain the notifications even when hot reload is disabled, not to apply stale ones later.
+    if model.settings_events.try_iter().count() > 0 && model.hot_reload {
+        load_settings(model, Path::new(SETTINGS_PATH));
+    }
+
+    update_screensaver(app, model);
+    if model.normalize_noise && model.field_kind == FieldKind::Noise {
+        track_noise_range(model, noise_z);
+    }
+
+    if model.running && model.frozen_z.is_none() {
+        model.depth += model.depth_speed * update.since_last.as_secs_f32();
+        model.max_angle_time += update.since_last.as_secs_f32();
+    }
+    update_field_version(model);
+    if model.cache_field {
+        update_field_grid(app, model, noise_z);
+    }
+
+    if model.enable_particles && !model.inspect_frozen {
+        // Fixed steps keep the motion the same whatever the frame rate, the time left being
+        // simulated on the next frames.
+        let step_duration = SIMULATION_FRAME_DURATION / model.substeps as f32;
+        if model.running {
+            model.simulation_lag += update.since_last.as_secs_f32().min(FRAME_TIME_MAX)
+                * model.particle_draw_speed as f32;
+        }
+        let steps = (model.simulation_lag / step_duration) as usize;
+        model.simulation_lag -= steps as f32 * step_duration;
+        update_particles(app, model, noise_z, steps, step_duration);
+    }
+
+    model.palette_offset = (app.time * model.palette_cycle_speed * 2.0 * PI) % (2.0 * PI);
+    if model.show_values && model.value_grid_texture {
+        update_value_texture(app, model, scene_rect(app, model), noise_z);
+    }
+
+    if model.fixed_resolution {
+        render_composite(app, model, noise_z);
+    }
+
+    if model.screenshot_requested {
+        model.capture_requested = true;
+    }
+    if model.capture_requested {
+        let path = if model.screenshot_requested {
+            model.screenshot_requested = false;
+            next_screenshot_path(model)
+        } else {
+            format!(
+                "capture_seed{}_freq{:.2}_{}.png",
+                model.seed,
+                model.frequency,
+                timestamp()
+            )
+        };
+        if let Some(region) = crop_region(app, model) {
+            model.pending_crop = Some((path.clone(), region));
+        }
+        app.main_window().capture_frame(path);
+    }
+
+    if model.recording {
+        if model.frame_index == 0 {
+            if let Err(error) = fs::create_dir_all(RECORDING_DIRECTORY) {
+                warn!("Cannot create {}: {}", RECORDING_DIRECTORY, error);
+                model.recording = false;
+            }
+        }
+        if model.recording {
+            model.frame_index += 1;
+            app.main_window().capture_frame(format!(
+                "{}/frame_{:05}.png",
+                RECORDING_DIRECTORY, model.frame_index
+            ));
+        }
+    }
+
+    if model.export_requested {
+        model.export_requested = false;
+        export_frame(app, model, noise_z);
+    }
+}
+
+/// The region PNG captures are cropped to, in pixels, if any.
+fn crop_region(app: &App, model: &Model) -> Option<[u32; 4]> {
+    let window = app.main_window();
+    let (width, height) = window.inner_size_pixels();
+    match model.crop_mode {
+        CropMode::Full => None,
+        CropMode::ExcludeGui => {
+            let gui = model.gui_rect?;
+            let scale = window.scale_factor();
+            let left = ((gui.left() * scale).max(0.0) as u32).min(width);
+            let right = ((gui.right() * scale).max(0.0) as u32).min(width);
+            if left >= width - right {
+                Some([0, 0, left, height])
+            } else {
+                Some([right, 0, width - right, height])
+            }
+        }
+        CropMode::Region => Some(model.crop_region),
+    }
+}
+
+/// Waits for the capture at `path` to be written, and crops it to `region`, clamped to the
+/// image.
+fn crop_capture(app: &App, path: &str, region: [u32; 4]) {
+    if let Err(error) = app.main_window().await_capture_frame_jobs() {
+        warn!("Cannot wait for {} to be written: {}", path, error);
+        return;
+    }
+    let capture = match image::open(path) {
+        Ok(capture) => capture,
+        Err(error) => {
+            warn!("Cannot read {}: {}", path, error);
+            return;
+        }
+    };
+    let [x, y, width, height] = region;
+    let x = x.min(capture.width());
+    let y = y.min(capture.height());
+    let cropped = capture.crop_imm(
+        x,
+        y,
+        width.min(capture.width() - x),
+        height.min(capture.height() - y),
+    );
+    if let Err(error) = cropped.save(path) {
+        warn!("Cannot write {}: {}", path, error);
+    }
+}
+
+/// Replaces the noise the field is sampled from.
+fn set_noise(model: &mut Model, noise: SharedNoise) {
+    model.noise = noise;
+    model.noise_version += 1;
+}
+
+fn update_screensaver(app: &App, model: &mut Model) {
+    let screensaver = &mut model.screensaver;
+    if !screensaver.enabled {
+        // Settle on the target field if disabled while fading.
+        if let Some((_, to)) = screensaver.fading.take() {
+            set_noise(model, to);
+        }
+        return;
+    }
+    let due = screensaver.switch_time.map_or(true, |switch_time| {
+        app.time - switch_time >= screensaver.interval
+    });
+    if due && !screensaver.order.is_empty() {
+        let seed = screensaver.order[screensaver.next_index % screensaver.order.len()];
+        screensaver.next_index = (screensaver.next_index + 1) % screensaver.order.len();
+        let to = model.noise_source.build(seed);
+        screensaver.fading = Some((model.noise.clone(), to));
+        screensaver.switch_time = Some(app.time);
+    }
+    let (fading, switch_time) = match (&screensaver.fading, screensaver.switch_time) {
+        (Some(fading), Some(switch_time)) => (fading.clone(), switch_time),
+        _ => return,
+    };
+    let mix = if screensaver.fade > 0.0 {
+        (app.time - switch_time) / screensaver.fade
+    } else {
+        1.0
+    };
+    if mix >= 1.0 {
+        screensaver.fading = None;
+        set_noise(model, fading.1);
+    } else {
+        let (from, to) = fading;
+        set_noise(
+            model,
+            Arc::new(Crossfade {
+                from,
+                to,
+                mix: mix as f64,
+            }),
+        );
+    }
+}
+
+/// Advances the particles by `steps` updates of `dt` seconds, drawing each step into the
+/// particle texture. The particles do not move while the field is paused or without steps.
+fn update_particles(app: &App, model: &mut Model, noise_z: f32, steps: usize, dt: f32) {
+    let draw = app.draw();
+    if model.persistent_canvas {
+        // Nothing to do, drawing over the previous content.
+    } else if model.trails {
+        // Scale the previous content, alpha included, down by the fade amount so that it
+        // progressively vanishes instead of being cleared.
+        let fade = wgpu::BlendComponent {
+            src_factor: wgpu::BlendFactor::Zero,
+            dst_factor: wgpu::BlendFactor::OneMinusSrcAlpha,
+            operation: wgpu::BlendOperation::Add,
+        };
+        let [w, h] = model.particle_texture.size();
+        draw.color_blend(fade)
+            .alpha_blend(fade)
+            .rect()
+            .w_h(w as f32, h as f32)
+            .color(rgba(0.0, 0.0, 0.0, model.trail_alpha));
+    } else {
+        draw.background().color(rgba(0.0, 0.0, 0.0, 0.0));
+    }
+    let params = field_params(model, noise_z);
+    let noise_a = noise_field(model, &model.noise);
+    let noise_b = noise_field(model, &model.noise_b);
+    let noise_field = BlendField {
+        from: &noise_a,
+        to: &noise_b,
+        blend: model.blend,
+    };
+    let field: &dyn FieldSource = match (&model.field_grid, model.field_kind) {
+        (Some((key, grid)), _) if key.params == params => grid,
+        (_, FieldKind::Noise) => &noise_field,
+        (_, FieldKind::Expression) => &model.expression_field,
+    };
+    let forces: Vec<ForceSource> = model
+        .push_position
+        .map(|position| ForceSource {
+            position,
+            radius: model.push_radius,
+            strength: model.push_strength,
+        })
+        .into_iter()
+        .collect();
+    if model.running && steps > 0 {
+        // Taken out of the model while they move, for the field to borrow it.
+        let mut particle_systems = mem::take(&mut model.particle_systems);
+        for _ in 0..steps {
+            for particle_system in &mut particle_systems {
+                particle_system.update(field, &params, &forces, dt);
+                particle_system.draw(&draw);
+            }
+        }
+        model.particle_systems = particle_systems;
+    } else {
+        // Paused, or waiting for the next fixed step, the particles are still drawn where they
+        // stand.
+        for particle_system in &model.particle_systems {
+            particle_system.draw(&draw);
+        }
+    }
+    let window = app.main_window();
+    let device = window.device();
+    let ce_desc = wgpu::CommandEncoderDescriptor {
+        label: Some("texture renderer"),
+    };
+    let mut encoder = device.create_command_encoder(&ce_desc);
+    match &model.particle_msaa_texture {
+        Some(msaa_texture) => model.renderer.encode_render_pass(
+            device,
+            &mut encoder,
+            &draw,
+            1.0,
+            msaa_texture.size(),
+            &msaa_texture.view().build(),
+            Some(&model.particle_texture.view().build()),
+        ),
+        None => {
+            model
+                .renderer
+                .render_to_texture(device, &mut encoder, &draw, &model.particle_texture)
+        }
+    }
+    window.queue().submit(Some(encoder.finish()));
+}
+
+/// Path of the next numbered screenshot, skipping the numbers taken by earlier sessions.
+fn next_screenshot_path(model: &mut Model) -> String {
+    loop {
+        model.screenshot_index += 1;
+        let path = format!("shot_{:04}.png", model.screenshot_index);
+        if !Path::new(&path).exists() {
+            return path;
+        }
+    }
+}
+
+/// Seconds since the Unix epoch, used to name exported files.
+fn timestamp() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default()
+}
+
+/// Running sum of the sub-frames of an exported frame, written once all of them are received.
+struct FrameAccumulator {
+    path: String,
+    expected: usize,
+    received: usize,
+    size: [u32; 2],
+    sum: Vec<f32>,
+}
+
+impl FrameAccumulator {
+    fn new(path: String, expected: usize, size: [u32; 2]) -> Self {
+        Self {
+            path,
+            expected,
+            received: 0,
+            size,
+            sum: vec![0.0; (size[0] * size[1] * 4) as usize],
+        }
+    }
+
+    fn add(&mut self, subframe: &image::RgbaImage) {
+        for (sum, value) in self.sum.iter_mut().zip(subframe.as_raw()) {
+            *sum += *value as f32;
+        }
+        self.received += 1;
+        if self.received == self.expected {
+            let average = self
+                .sum
+                .iter()
+                .map(|sum| (sum / self.expected as f32).round() as u8)
+                .collect();
+            let [width, height] = self.size;
+            let frame = image::RgbaImage::from_raw(width, height, average)
+                .expect("accumulator size matches the frame size");
+            if let Err(error) = frame.save(&self.path) {
+                warn!("Failed to write {}: {}", self.path, error);
+            }
+        }
+    }
+}
+
+/// Renders the scene offscreen, averaging `export_subframes` sub-frames spread over one frame
+/// duration, and writes the result as a PNG in the working directory.
+fn export_frame(app: &App, model: &mut Model, noise_z: f32) {
+    let win = scene_rect(app, model);
+    let size = [win.w() as u32, win.h() as u32];
+    if model.export_texture.size() != size {
+        let window = app.main_window();
+        model.export_texture = render_texture(window.device(), size);
+        model.export_renderer = texture_renderer(window.device(), &model.export_texture);
+        info!("Rebuilt the export texture at {}x{}", size[0], size[1]);
+    }
+    let subframes = model.export_subframes;
+    let z_step = if model.running && model.frozen_z.is_none() {
+        model.speed * EXPORT_FRAME_DURATION / subframes as f32
+    } else {
+        0.0
+    };
+    let accumulator = Arc::new(Mutex::new(FrameAccumulator::new(
+        format!("frame_{}.png", timestamp()),
+        subframes,
+        model.export_texture.size(),
+    )));
+    for subframe in 0..subframes {
+        let subframe_z = noise_z + subframe as f32 * z_step;
+        if subframe > 0 && model.enable_particles && !model.inspect_frozen {
+            update_particles(
+                app,
+                model,
+                subframe_z,
+                1,
+                EXPORT_FRAME_DURATION / subframes as f32,
+            );
+        }
+        let draw = Draw::new();
+        draw_scene(&draw, model, win, &field_params(model, subframe_z));
+        let window = app.main_window();
+        let device = window.device();
+        let ce_desc = wgpu::CommandEncoderDescriptor {
+            label: Some("export renderer"),
+        };
+        let mut encoder = device.create_command_encoder(&ce_desc);
+        model
+            .export_renderer
+            .render_to_texture(device, &mut encoder, &draw, &model.export_texture);
+        let snapshot = model
+            .texture_capturer
+            .capture(device, &mut encoder, &model.export_texture);
         window.queue().submit(Some(encoder.finish()));
+        let accumulator = accumulator.clone();
+        let read = snapshot.read(move |result| {
+            // A missing subframe leaves the frame incomplete, so it is never written.
+            let subframe = match result {
+                Ok(subframe) => subframe.to_owned(),
+                Err(error) => {
+                    warn!(
+                        "Failed to map an exported subframe, dropping it: {:?}",
+                        error
+                    );
+                    return;
+                }
+            };
+            match accumulator.lock() {
+                Ok(mut accumulator) => accumulator.add(&subframe),
+                Err(_) => warn!("Dropping an exported subframe after a failed one"),
+            }
+        });
+        if read.is_err() {
+            warn!("Timed out waiting for a capture worker, dropping the exported frame");
+            return;
+        }
+    }
+}
+
+/// The rect the scene is laid out in: the window, or the internal resolution when fixed.
+fn scene_rect(app: &App, model: &Model) -> Rect {
+    if model.fixed_resolution {
+        let [width, height] = model.internal_resolution;
+        Rect::from_w_h(width as f32, height as f32)
+    } else {
+        app.window_rect()
+    }
+}
+
+/// Renders the scene at the internal resolution, rebuilding the target when the resolution changed.
+fn render_composite(app: &App, model: &mut Model, noise_z: f32) {
+    let window = app.main_window();
+    let device = window.device();
+    if model.composite_texture.size() != model.internal_resolution {
+        model.composite_texture = render_texture(device, model.internal_resolution);
+        model.composite_renderer = texture_renderer(device, &model.composite_texture);
+        info!(
+            "Rebuilt the composite texture at {}x{}",
+            model.internal_resolution[0], model.internal_resolution[1]
+        );
     }
+    let draw = Draw::new();
+    let params = field_params(model, noise_z);
+    draw_scene(&draw, model, scene_rect(app, model), &params);
+    let ce_desc = wgpu::CommandEncoderDescriptor {
+        label: Some("composite renderer"),
+    };
+    let mut encoder = device.create_command_encoder(&ce_desc);
+    model.composite_renderer.render_to_texture(
+        device,
+        &mut encoder,
+        &draw,
+        &model.composite_texture,
+    );
+    window.queue().submit(Some(encoder.finish()));
+}
+
+/// Bakes the value grid into a texture holding one texel per cell, only when the field changed.
+fn update_value_texture(app: &App, model: &mut Model, win: Rect, noise_z: f32) {
+    let key = ValueGridKey {
+        params: field_params(model, noise_z),
+        source: FieldSourceKey::from_model(model),
+        step: grid_step(model, win),
+        colormap: model.colormap,
+        value_mode: model.value_mode,
+        palette_offset: model.palette_offset,
+        window_size: [win.w() as u32, win.h() as u32],
+        flat_threshold: model.hide_flat_cells.then(|| model.magnitude_threshold),
+        mask_version: model.mask_version,
+    };
+    if model.value_texture_key.as_ref() == Some(&key) {
+        return;
+    }
+    let step = grid_step(model, win);
+    let columns = (win.left() as i32..win.right() as i32)
+        .step_by(step)
+        .count() as u32;
+    let rows = (win.bottom() as i32..win.top() as i32)
+        .step_by(step)
+        .count() as u32;
+    let grid = image::RgbaImage::from_fn(columns, rows, |column, row| {
+        // Texture rows go top to bottom while the grid goes bottom to top.
+        let canvas_point = Vec2::new(
+            (win.left() as i32 + (column as usize * step) as i32) as f32,
+            (win.bottom() as i32 + ((rows - 1 - row) as usize * step) as i32) as f32,
+        );
+        if is_masked_out(model, win, canvas_point)
+            || model.hide_flat_cells && is_flat(model, win, canvas_point, &key.params)
+        {
+            return image::Rgba([0, 0, 0, 0]);
+        }
+        let color: Srgba<u8> =
+            Srgba::from_linear(value_color(model, win, canvas_point, &key.params)).into_format();
+        image::Rgba([color.red, color.green, color.blue, color.alpha])
+    });
+    model.value_texture = Some(wgpu::Texture::from_image(
+        app,
+        &image::DynamicImage::ImageRgba8(grid),
+    ));
+    debug!("Rebuilt the value grid texture, {}x{} cells", columns, rows);
+    model.value_texture_key = Some(key);
+}
+
+fn field_version(model: &Model) -> u64 {
+    model
+        .field_source
+        .as_ref()
+        .map_or(0, |(_, version)| *version)
+}
+
+/// Gives the particle systems a new field version when the field changed besides its
+/// parameters.
+fn update_field_version(model: &mut Model) {
+    let key = FieldSourceKey::from_model(model);
+    if matches!(&model.field_source, Some((source, _)) if *source == key) {
+        return;
+    }
+    let version = field_version(model) + 1;
+    for particle_system in &mut model.particle_systems {
+        particle_system.set_field_version(version);
+    }
+    model.field_source = Some((key, version));
+}
+
+/// Bakes the field into the cached grid, only when the field changed.
+fn update_field_grid(app: &App, model: &mut Model, noise_z: f32) {
+    // The grid covers the unzoomed window, the zoomed field is sampled directly.
+    if model.viewport != Viewport::default() {
+        model.field_grid = None;
+        return;
+    }
+    let win = scene_rect(app, model);
+    let rows = (FIELD_GRID_COLUMNS as f32 * win.h() / win.w()).round() as usize;
+    let key = FieldGridKey {
+        params: field_params(model, noise_z),
+        source: FieldSourceKey::from_model(model),
+        size: [FIELD_GRID_COLUMNS, rows],
+    };
+    if matches!(&model.field_grid, Some((cached, _)) if *cached == key) {
+        return;
+    }
+    let noise_a = noise_field(model, &model.noise);
+    let noise_b = noise_field(model, &model.noise_b);
+    let noise_field = BlendField {
+        from: &noise_a,
+        to: &noise_b,
+        blend: model.blend,
+    };
+    let field: &dyn FieldSource = match model.field_kind {
+        FieldKind::Noise => &noise_field,
+        FieldKind::Expression => &model.expression_field,
+    };
+    let grid = FieldGrid::bake(field, &key.params, FIELD_GRID_COLUMNS, rows);
+    model.field_grid = Some((key, grid));
+}
+
+/// Formats the current settings as a `Settings` literal, to bake them back into its `Default`
+/// implementation.
+fn rust_snippet(model: &Model) -> String {
+    rust_code::to_string(&Settings::from_model(model))
+        .unwrap_or_else(|error| format!("// Cannot format the settings: {}", error))
 }
 
 fn noise_z(app: &App, model: &Model) -> f64 {
-    if model.running {
+    if let Some(z) = model.frozen_z {
+        z as f64
+    } else if model.running {
         (app.time * model.speed - model.reference_time) as f64
     } else {
         model.reference_time as f64
     }
 }
 
+/// The `reference_time` resuming the field at time `z` when unfrozen.
+fn resumed_reference_time(app: &App, running: bool, speed: f32, z: f32) -> f32 {
+    if running {
+        app.time * speed - z
+    } else {
+        z
+    }
+}
+
+/// Distance between two grid nodes in `win`, in pixels.
+fn grid_step(model: &Model, win: Rect) -> usize {
+    match model.grid_mode {
+        GridMode::Pixels => model.step_sample,
+        GridMode::Columns => ((win.w() / model.step_sample as f32).round() as usize).max(1),
+    }
+}
+
+/// The max angle, oscillating around `max_angle` when animated.
+fn max_angle(model: &Model) -> Radian {
+    if !model.animate_max_angle {
+        return model.max_angle;
+    }
+    let phase = 2.0 * PI * model.max_angle_time / model.max_angle_period;
+    model.max_angle + model.max_angle_amplitude * phase.sin()
+}
+
+/// Parameters of the field at time `noise_z`, shared by the arrows and the particles.
+///
+/// The time is left at 0 for the still 2D noise, so that the cached grids are not rebuilt as
+/// it runs.
+fn field_params(model: &Model, noise_z: f32) -> FieldParams {
+    let still =
+        model.field_kind == FieldKind::Noise && model.noise_dimensions == NoiseDimensions::Two;
+    FieldParams {
+        frequency: model.frequency,
+        max_angle: max_angle(model),
+        z: if still { 0.0 } else { noise_z },
+        orientation: model.orientation,
+        viewport: model.viewport,
+    }
+}
+
+/// The field of `noise`, one of the model noises, as set up in the model.
+fn noise_field<'a>(model: &'a Model, noise: &'a SharedNoise) -> NoiseField<'a> {
+    NoiseField {
+        noise: &**noise,
+        dimensions: model.noise_dimensions,
+        fbm: model.fbm,
+        depth: model.depth as f64,
+        warp: model.warp_strength,
+        domain: model.domain,
+        range: model.noise_range,
+        remap: model.remap_curve,
+    }
+}
+
+/// Angle of the active field at normalized coordinates, see `FieldSource`.
+fn field_angle_at(model: &Model, x: f32, y: f32, params: &FieldParams) -> Radian {
+    if let Some((key, grid)) = &model.field_grid {
+        if key.params == *params {
+            return grid.angle_at(x, y, params);
+        }
+    }
+    match model.field_kind {
+        FieldKind::Noise => BlendField {
+            from: &noise_field(model, &model.noise),
+            to: &noise_field(model, &model.noise_b),
+            blend: model.blend,
+        }
+        .angle_at(x, y, params),
+        FieldKind::Expression => model.expression_field.angle_at(x, y, params),
+    }
+}
+
+/// Widens or shrinks `noise_range` toward the range of both noises over a grid of samples.
+fn track_noise_range(model: &mut Model, noise_z: f32) {
+    let params = field_params(model, noise_z);
+    let mut observed = NoiseRange {
+        min: f32::INFINITY,
+        max: f32::NEG_INFINITY,
+    };
+    for noise in [&model.noise, &model.noise_b] {
+        let field = NoiseField {
+            range: None,
+            ..noise_field(model, noise)
+        };
+        for column in 0..NOISE_RANGE_SAMPLES {
+            for row in 0..NOISE_RANGE_SAMPLES {
+                let value = field.value_at(
+                    (column as f32 + 0.5) / NOISE_RANGE_SAMPLES as f32,
+                    (row as f32 + 0.5) / NOISE_RANGE_SAMPLES as f32,
+                    &params,
+                );
+                observed.min = observed.min.min(value);
+                observed.max = observed.max.max(value);
+            }
+        }
+    }
+    match &mut model.noise_range {
+        Some(range) => range.track(observed, NOISE_RANGE_DECAY),
+        None => model.noise_range = Some(observed),
+    }
+}
+
+/// Angle of the vector field at `point`, a position in window coordinates.
+fn field_angle(model: &Model, win: Rect, point: Vec2, params: &FieldParams) -> Radian {
+    let perlin = normalized_position(win, point, params);
+    field_angle_at(model, perlin.x, perlin.y, params)
+}
+
+/// Integrates the field from `start` with the fourth order Runge-Kutta method, stopping after
+/// `steps` steps or when leaving `win`.
+fn streamline(
+    model: &Model,
+    win: Rect,
+    start: Vec2,
+    steps: usize,
+    params: &FieldParams,
+) -> Vec<Vec2> {
+    let direction = |point: Vec2| Vec2::new(1., 0.).rotate(field_angle(model, win, point, params));
+    let h = STREAMLINE_STEP_LENGTH;
+    let mut points = vec![start];
+    let mut point = start;
+    for _ in 0..steps {
+        let k1 = direction(point);
+        let k2 = direction(point + k1 * h / 2.0);
+        let k3 = direction(point + k2 * h / 2.0);
+        let k4 = direction(point + k3 * h);
+        point += (k1 + 2.0 * k2 + 2.0 * k3 + k4) * h / 6.0;
+        if !win.contains(point) {
+            break;
+        }
+        points.push(point);
+    }
+    points
+}
+
+/// Finds the grid cells where both components of the field vector change sign.
+///
+/// The field vectors are unit vectors, so they never vanish exactly: such cells are where the
+/// direction covers opposite quadrants, the discrete counterpart of a zero of the field.
+fn critical_points(model: &Model, win: Rect, params: &FieldParams) -> Vec<(Vec2, CriticalPoint)> {
+    let step = grid_step(model, win) as f32;
+    let columns = (win.w() / step).ceil() as usize;
+    let rows = (win.h() / step).ceil() as usize;
+    let node = |column: usize, row: usize| {
+        Vec2::new(
+            win.left() + column as f32 * step,
+            win.bottom() + row as f32 * step,
+        )
+    };
+    let vectors: Vec<Vec2> = (0..=rows)
+        .flat_map(|row| (0..=columns).map(move |column| (column, row)))
+        .map(|(column, row)| {
+            Vec2::new(1.0, 0.0).rotate(field_angle(model, win, node(column, row), params))
+        })
+        .collect();
+    let vector = |column: usize, row: usize| vectors[row * (columns + 1) + column];
+    let mut points = vec![];
+    for row in 0..rows {
+        for column in 0..columns {
+            let [v00, v10, v01, v11] = [
+                vector(column, row),
+                vector(column + 1, row),
+                vector(column, row + 1),
+                vector(column + 1, row + 1),
+            ];
+            let changes_sign = |component: fn(&Vec2) -> f32| {
+                let values = [v00, v10, v01, v11].map(|v| component(&v));
+                values.iter().any(|value| *value < 0.0) && values.iter().any(|value| *value > 0.0)
+            };
+            if !(changes_sign(|v| v.x) && changes_sign(|v| v.y)) {
+                continue;
+            }
+            let d_dx = ((v10 + v11) - (v00 + v01)) / (2.0 * step);
+            let d_dy = ((v01 + v11) - (v00 + v10)) / (2.0 * step);
+            let determinant = d_dx.x * d_dy.y - d_dy.x * d_dx.y;
+            let trace = d_dx.x + d_dy.y;
+            let kind = if determinant < 0.0 {
+                CriticalPoint::Saddle
+            } else if trace > 0.0 {
+                CriticalPoint::Source
+            } else {
+                CriticalPoint::Sink
+            };
+            points.push((node(column, row) + Vec2::splat(step / 2.0), kind));
+        }
+    }
+    points
+}
+
+/// Edge of the arrow grid, from the node at the column and row to the next one on the right
+/// when horizontal, or above otherwise.
+type GridEdge = (usize, usize, bool);
+
+/// Iso-contours of the field angle, traced by marching squares over the arrow grid.
+fn contours(model: &Model, win: Rect, params: &FieldParams) -> Vec<Vec<Vec2>> {
+    let step = grid_step(model, win) as f32;
+    let columns = (win.w() / step).ceil() as usize;
+    let rows = (win.h() / step).ceil() as usize;
+    let node = |column: usize, row: usize| {
+        Vec2::new(
+            win.left() + column as f32 * step,
+            win.bottom() + row as f32 * step,
+        )
+    };
+    let values: Vec<f32> = (0..=rows)
+        .flat_map(|row| (0..=columns).map(move |column| (column, row)))
+        .map(|(column, row)| field_angle(model, win, node(column, row), params))
+        .collect();
+    let value = |column: usize, row: usize| values[row * (columns + 1) + column];
+    let min = values.iter().copied().fold(f32::INFINITY, f32::min);
+    let max = values.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+    let mut polylines = vec![];
+    for level_index in 1..=model.contour_count {
+        let level = min + (max - min) * level_index as f32 / (model.contour_count + 1) as f32;
+        let crossing = |(column, row, horizontal): GridEdge| {
+            let (next_column, next_row) = if horizontal {
+                (column + 1, row)
+            } else {
+                (column, row + 1)
+            };
+            let (from, to) = (value(column, row), value(next_column, next_row));
+            node(column, row).lerp(node(next_column, next_row), (level - from) / (to - from))
+        };
+        // Each crossed edge is linked to the crossed edges of the one or two cells it borders.
+        let mut links: HashMap<GridEdge, Vec<GridEdge>> = HashMap::new();
+        for row in 0..rows {
+            for column in 0..columns {
+                // Counterclockwise from the bottom left corner, edge `i` joining corners `i` and
+                // `i + 1`.
+                let corners = [
+                    value(column, row),
+                    value(column + 1, row),
+                    value(column + 1, row + 1),
+                    value(column, row + 1),
+                ];
+                let edges = [
+                    (column, row, true),
+                    (column + 1, row, false),
+                    (column, row + 1, true),
+                    (column, row, false),
+                ];
+                let crossed: Vec<GridEdge> = (0..4)
+                    .filter(|&i| (corners[i] >= level) != (corners[(i + 1) % 4] >= level))
+                    .map(|i| edges[i])
+                    .collect();
+                let pairs = match crossed[..] {
+                    [a, b] => vec![(a, b)],
+                    // A saddle, the corners on the side of the cell center being joined.
+                    [a, b, c, d] => {
+                        let center = corners.iter().sum::<f32>() / 4.0;
+                        if (center >= level) == (corners[0] >= level) {
+                            vec![(a, b), (c, d)]
+                        } else {
+                            vec![(d, a), (b, c)]
+                        }
+                    }
+                    _ => vec![],
+                };
+                for (a, b) in pairs {
+                    links.entry(a).or_default().push(b);
+                    links.entry(b).or_default().push(a);
+                }
+            }
+        }
+        // Open contours are walked from one of their ends, closed ones from anywhere.
+        let mut starts: Vec<GridEdge> = links
+            .iter()
+            .filter(|(_, linked)| linked.len() == 1)
+            .map(|(edge, _)| *edge)
+            .collect();
+        starts.extend(links.keys().copied());
+        let mut visited = HashSet::new();
+        for start in starts {
+            if !visited.insert(start) {
+                continue;
+            }
+            let mut polyline = vec![crossing(start)];
+            let mut current = start;
+            while let Some(&next) = links[&current].iter().find(|edge| !visited.contains(*edge)) {
+                visited.insert(next);
+                polyline.push(crossing(next));
+                current = next;
+            }
+            if links[&start].len() == 2 && links[&current].contains(&start) {
+                polyline.push(polyline[0]);
+            }
+            polylines.push(polyline);
+        }
+    }
+    polylines
+}
+
+/// Gradient of the field angle at `point`, a position in window coordinates, in turns per
+/// window as shown, zoomed.
+fn angle_gradient(model: &Model, win: Rect, point: Vec2, params: &FieldParams) -> Vec2 {
+    let perlin = normalized_position(win, point, params);
+    let h = GRADIENT_EPSILON;
+    let sample = |x: f32, y: f32| field_angle_at(model, x, y, params) / (2.0 * PI);
+    Vec2::new(
+        sample(perlin.x + h, perlin.y) - sample(perlin.x - h, perlin.y),
+        sample(perlin.x, perlin.y + h) - sample(perlin.x, perlin.y - h),
+    ) / (2.0 * h * params.viewport.zoom)
+}
+
+/// Divergence and curl of the field direction at `point`, a position in window coordinates,
+/// per window width.
+///
+/// A positive divergence spreads the particles out, a negative one pools them, and a positive
+/// curl swirls them counterclockwise.
+fn divergence_curl(model: &Model, win: Rect, point: Vec2, params: &FieldParams) -> (f32, f32) {
+    let h = GRADIENT_EPSILON * win.w();
+    let direction =
+        |offset: Vec2| Vec2::new(1., 0.).rotate(field_angle(model, win, point + offset, params));
+    let right = direction(Vec2::new(h, 0.));
+    let left = direction(Vec2::new(-h, 0.));
+    let top = direction(Vec2::new(0., h));
+    let bottom = direction(Vec2::new(0., -h));
+    let scale = win.w() / (2.0 * h);
+    let divergence = (right.x - left.x + top.y - bottom.y) * scale;
+    let curl = (right.y - left.y - top.x + bottom.x) * scale;
+    (divergence, curl)
+}
+
+/// Gradient of the field angle at `point`, a position in window coordinates, as a vector in
+/// window space, in turns per window width.
+fn screen_gradient(model: &Model, win: Rect, point: Vec2, params: &FieldParams) -> Vec2 {
+    let gradient = angle_gradient(model, win, point, params);
+    let direction = params.orientation.screen_direction();
+    Vec2::new(
+        gradient.x * direction.x,
+        gradient.y * direction.y * win.w() / win.h(),
+    )
+}
+
+/// Color of the value grid cell at `point`, a position in window coordinates.
+fn value_color(model: &Model, win: Rect, point: Vec2, params: &FieldParams) -> LinSrgba {
+    match model.value_mode {
+        ValueMode::Angle => model
+            .colormap
+            .sample_angle(field_angle(model, win, point, params) + model.palette_offset),
+        ValueMode::Magnitude => model
+            .colormap
+            .sample(angle_gradient(model, win, point, params).length() / MAGNITUDE_MAX),
+    }
+}
+
 fn view(app: &App, model: &Model, frame: Frame) {
     let draw = app.draw();
-    let step = model.step_sample;
-    let arrow_width = (step - 2) as f32;
-    let stroke_weight = 2.;
-    let max_angle = model.max_angle;
-    let win = app.window_rect();
-    let perlin_z = noise_z(app, model);
+    if model.fixed_resolution {
+        let win = app.window_rect();
+        let [width, height] = model.composite_texture.size();
+        let (scale_x, scale_y) = (win.w() / width as f32, win.h() / height as f32);
+        let scale = match model.resolution_fit {
+            ResolutionFit::Fit => scale_x.min(scale_y),
+            ResolutionFit::Fill => scale_x.max(scale_y),
+        };
+        draw.background().color(LETTERBOX_COLOR);
+        draw.texture(&model.composite_texture)
+            .w_h(width as f32 * scale, height as f32 * scale);
+    } else {
+        let params = field_params(model, noise_z(app, model) as f32);
+        draw_scene(&draw, model, app.window_rect(), &params);
+    }
+    draw.to_frame(app, &frame).unwrap();
+    // Keep the GUI out of captured frames.
+    if !model.capture_requested {
+        model.egui.draw_to_frame(&frame).unwrap();
+    }
+}
 
-    draw.background().color(BACKGROUND_COLOR);
+/// Draws the field and the particle layer, without the GUI.
+fn draw_scene(draw: &Draw, model: &Model, win: Rect, params: &FieldParams) {
+    let step = grid_step(model, win);
+
+    draw.background().color(model.background_color);
+
+    let value_texture = match &model.value_texture {
+        Some(texture) if model.show_values && model.value_grid_texture => Some(texture),
+        _ => None,
+    };
+    if let Some(texture) = value_texture {
+        let [columns, rows] = texture.size();
+        let grid_w = (columns as usize * step) as f32;
+        let grid_h = (rows as usize * step) as f32;
+        let sampler = wgpu::SamplerBuilder::new()
+            .mag_filter(model.value_grid_filter.filter_mode())
+            .min_filter(model.value_grid_filter.filter_mode())
+            .into_descriptor();
+        draw.sampler(sampler)
+            .texture(texture)
+            .w_h(grid_w, grid_h)
+            .x_y(
+                win.left() as i32 as f32 + grid_w / 2.0,
+                win.bottom() as i32 as f32 + grid_h / 2.0,
+            );
+    }
+
+    if model.show_values && value_texture.is_none() {
+        for canvas_x in (win.left() as i32..win.right() as i32).step_by(step) {
+            for canvas_y in (win.bottom() as i32..win.top() as i32).step_by(step) {
+                let canvas_point = Vec2::new(canvas_x as f32, canvas_y as f32);
+                if is_masked_out(model, win, canvas_point)
+                    || model.hide_flat_cells && is_flat(model, win, canvas_point, params)
+                {
+                    continue;
+                }
+                let color = value_color(model, win, canvas_point, params);
+                draw.rect().color(color).w(step as f32).h(step as f32).x_y(
+                    canvas_x as f32 + step as f32 / 2.0,
+                    canvas_y as f32 + step as f32 / 2.0,
+                );
+            }
+        }
+    }
+
+    if model.arrows_on_top {
+        draw_particle_layer(draw, model, win);
+        draw_arrows(draw, model, win, params);
+    } else {
+        draw_arrows(draw, model, win, params);
+        draw_particle_layer(draw, model, win);
+    }
+    if model.show_density {
+        draw_density(draw, model, win);
+    }
+}
+
+/// Draws the arrows, streamlines and critical points of the field.
+fn draw_arrows(draw: &Draw, model: &Model, win: Rect, params: &FieldParams) {
+    let step = grid_step(model, win);
+    let stroke_weight = model.stroke_weight;
 
     for canvas_x in (win.left() as i32..win.right() as i32).step_by(step) {
         for canvas_y in (win.bottom() as i32..win.top() as i32).step_by(step) {
-            let perlin_x = (win.right() - canvas_x as f32) / win.w();
-            let perlin_y = (win.top() - canvas_y as f32) / win.h();
-            let noise_angle = model.noise.get([
-                (perlin_x * model.frequency) as f64,
-                (perlin_y * model.frequency) as f64,
-                perlin_z,
-            ]) as f32
-                * max_angle;
-            let gradient = Vec2::new(1., 0.).rotate(noise_angle as f32) * arrow_width;
             let canvas_point = Vec2::new(canvas_x as f32, canvas_y as f32);
-            let offset = Vec2::new(gradient.x / 2., gradient.y / 2.);
-            if model.show_values {
-                let color = match model.angle_color {
-                    AngleColor::Gray => {
-                        let gray = (noise_angle.cos() + 1.0) / 2.0;
-                        Rgb::new(gray, gray, gray).into_lin_srgba()
-                    }
-                    AngleColor::HSV => {
-                        Hsv::new(noise_angle * 360.0 / (2. * PI), 1.0, 1.0).into_lin_srgba()
-                    }
-                };
-                draw.rect().color(color).w(step as f32).h(step as f32).x_y(
+            if is_masked_out(model, win, canvas_point) {
+                continue;
+            }
+            if model.show_arrows && !is_flat(model, win, canvas_point, params) {
+                let (start, end, weight) = arrow(model, win, canvas_point, step, params);
+                if model.arrow_heads {
+                    draw.arrow()
+                        .start(start)
+                        .end(end)
+                        .stroke_weight(weight)
+                        .caps_round()
+                        .color(model.arrow_color);
+                } else {
+                    draw.line()
+                        .start(start)
+                        .end(end)
+                        .stroke_weight(weight)
+                        .caps_round()
+                        .color(model.arrow_color);
+                }
+            }
+        }
+    }
+    if model.show_streamlines {
+        for canvas_x in (win.left() as i32..win.right() as i32).step_by(step) {
+            for canvas_y in (win.bottom() as i32..win.top() as i32).step_by(step) {
+                let start = Vec2::new(
                     canvas_x as f32 + step as f32 / 2.0,
                     canvas_y as f32 + step as f32 / 2.0,
                 );
+                if is_masked_out(model, win, start) {
+                    continue;
+                }
+                let points = streamline(model, win, start, model.streamline_steps, params);
+                draw.polyline()
+                    .weight(stroke_weight / 2.0)
+                    .points(points)
+                    .color(model.arrow_color);
+            }
+        }
+    }
+    if model.show_critical_points {
+        for (point, kind) in critical_points(model, win, params) {
+            draw.ellipse()
+                .xy(point)
+                .radius(step as f32 / 4.0)
+                .no_fill()
+                .stroke(kind.color())
+                .stroke_weight(stroke_weight);
+        }
+    }
+    if model.show_contours {
+        for points in contours(model, win, params) {
+            draw.polyline()
+                .weight(stroke_weight)
+                .points(points)
+                .color(model.contour_color);
+        }
+    }
+}
+
+fn draw_particle_layer(draw: &Draw, model: &Model, win: Rect) {
+    // The particle layer is sampled in coordinates normalized to its container, so stretching it
+    // keeps it aligned with the field when the scene is laid out in a different rect.
+    draw.texture(&model.particle_texture).w_h(win.w(), win.h());
+}
+
+/// Whether the angle gradient magnitude at `point` is below the threshold hiding the arrows.
+fn is_flat(model: &Model, win: Rect, point: Vec2, params: &FieldParams) -> bool {
+    model.magnitude_threshold > 0.0
+        && angle_gradient(model, win, point, params).length() < model.magnitude_threshold
+}
+
+/// Whether `point` lies out of the mask, when one is loaded.
+fn is_masked_out(model: &Model, win: Rect, point: Vec2) -> bool {
+    model
+        .mask
+        .as_ref()
+        .map_or(false, |mask| !mask.contains(win, point))
+}
+
+/// Start, end and stroke weight of the arrow at `point`, in a grid of `step` pixels.
+fn arrow(
+    model: &Model,
+    win: Rect,
+    point: Vec2,
+    step: usize,
+    params: &FieldParams,
+) -> (Vec2, Vec2, f32) {
+    let arrow_width = (step - 2) as f32;
+    // Weak regions get shorter and thinner arrows, never longer than their cell.
+    let (direction, scale) = match model.vector_mode {
+        VectorMode::Angle => {
+            let scale = if model.scale_by_magnitude {
+                let magnitude = angle_gradient(model, win, point, params).length();
+                (magnitude / MAGNITUDE_MAX).min(1.0)
+            } else {
+                1.0
+            };
+            (
+                Vec2::new(1., 0.).rotate(field_angle(model, win, point, params)),
+                scale,
+            )
+        }
+        VectorMode::Gradient => {
+            let gradient = screen_gradient(model, win, point, params);
+            (
+                gradient.normalize_or_zero(),
+                (gradient.length() / MAGNITUDE_MAX).min(1.0),
+            )
+        }
+    };
+    let offset = direction * arrow_width * scale / 2.;
+    let (start, end) = match model.arrow_anchor {
+        ArrowAnchor::Center => (point - offset, point + offset),
+        ArrowAnchor::Base => (point, point + 2.0 * offset),
+    };
+    (start, end, model.stroke_weight * scale.max(0.5))
+}
+
+/// Writes the arrow grid, over the value cells when shown, as an SVG file in the working
+/// directory. The particle layer is left out.
+fn export_svg(app: &App, model: &Model, noise_z: f32) {
+    let win = scene_rect(app, model);
+    let params = field_params(model, noise_z);
+    let step = grid_step(model, win);
+    // SVG coordinates start at the top left corner and grow downward.
+    let to_svg = |point: Vec2| (point.x - win.left(), win.top() - point.y);
+    let hex =
+        |color: rgb::Srgb<u8>| format!("#{:02x}{:02x}{:02x}", color.red, color.green, color.blue);
+    let mut cells = String::new();
+    let mut arrows = String::new();
+    for canvas_x in (win.left() as i32..win.right() as i32).step_by(step) {
+        for canvas_y in (win.bottom() as i32..win.top() as i32).step_by(step) {
+            let canvas_point = Vec2::new(canvas_x as f32, canvas_y as f32);
+            if is_masked_out(model, win, canvas_point) {
+                continue;
+            }
+            let flat = is_flat(model, win, canvas_point, &params);
+            if model.show_values && !(flat && model.hide_flat_cells) {
+                let color: Srgba<u8> =
+                    Srgba::from_linear(value_color(model, win, canvas_point, &params))
+                        .into_format();
+                let (x, y) = to_svg(canvas_point + Vec2::new(0.0, step as f32));
+                cells += &format!(
+                    "<rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" fill=\"{}\" fill-opacity=\"{:.3}\"/>\n",
+                    x,
+                    y,
+                    step,
+                    step,
+                    hex(color.color),
+                    color.alpha as f32 / 255.0
+                );
             }
+            if model.show_arrows && !flat {
+                let (start, end, weight) = arrow(model, win, canvas_point, step, &params);
+                let ((x1, y1), (x2, y2)) = (to_svg(start), to_svg(end));
+                arrows += &format!(
+                    "<line x1=\"{:.2}\" y1=\"{:.2}\" x2=\"{:.2}\" y2=\"{:.2}\" stroke-width=\"{:.2}\"/>\n",
+                    x1, y1, x2, y2, weight
+                );
+            }
+        }
+    }
+    let svg = format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{width}\" height=\"{height}\">\n\
+         <defs><marker id=\"arrowhead\" viewBox=\"0 0 10 10\" refX=\"10\" refY=\"5\" \
+         markerWidth=\"4\" markerHeight=\"4\" orient=\"auto\">\
+         <path d=\"M 0 0 L 10 5 L 0 10 z\" fill=\"{arrow}\"/></marker></defs>\n\
+         <rect width=\"100%\" height=\"100%\" fill=\"{background}\"/>\n\
+         {cells}\
+         <g stroke=\"{arrow}\" stroke-linecap=\"round\"{marker}>\n{arrows}</g>\n\
+         </svg>\n",
+        width = win.w(),
+        height = win.h(),
+        arrow = hex(model.arrow_color),
+        background = hex(model.background_color),
+        cells = cells,
+        arrows = arrows,
+        marker = if model.arrow_heads {
+            " marker-end=\"url(#arrowhead)\""
+        } else {
+            ""
+        },
+    );
+    let path = format!("field_{}.svg", timestamp());
+    if let Err(error) = fs::write(&path, svg) {
+        warn!("Cannot write {}: {}", path, error);
+    }
+}
 
-            if model.show_arrows {
-                draw.arrow()
-                    .start(canvas_point - offset)
-                    .end(canvas_point + offset)
-                    .stroke_weight(stroke_weight)
-                    .color(ARROW_COLOR);
+/// Draws the particle density histogram, each bin opacity being its count relative to the
+/// fullest bin.
+fn draw_density(draw: &Draw, model: &Model, win: Rect) {
+    // Binned in coordinates normalized to the particle texture, like the particle layer.
+    let [texture_w, texture_h] = model.particle_texture.size();
+    let columns = DENSITY_COLUMNS;
+    let rows = ((columns as f32 * texture_h as f32 / texture_w as f32).round() as usize).max(1);
+    let mut bins = vec![0_u32; columns * rows];
+    let positions = model
+        .particle_systems
+        .iter()
+        .flat_map(|particle_system| particle_system.positions());
+    for position in positions {
+        let x = position.x / texture_w as f32 + 0.5;
+        let y = position.y / texture_h as f32 + 0.5;
+        if !(0.0..1.0).contains(&x) || !(0.0..1.0).contains(&y) {
+            continue;
+        }
+        bins[(y * rows as f32) as usize * columns + (x * columns as f32) as usize] += 1;
+    }
+    let max = bins.iter().copied().max().unwrap_or(0).max(1) as f32;
+    let (bin_w, bin_h) = (win.w() / columns as f32, win.h() / rows as f32);
+    for row in 0..rows {
+        for column in 0..columns {
+            let count = bins[row * columns + column];
+            if count == 0 {
+                continue;
             }
+            draw.rect()
+                .w_h(bin_w, bin_h)
+                .x_y(
+                    win.left() + (column as f32 + 0.5) * bin_w,
+                    win.bottom() + (row as f32 + 0.5) * bin_h,
+                )
+                .color(rgba(1.0, 1.0, 1.0, DENSITY_ALPHA_MAX * count as f32 / max));
         }
     }
-    draw.texture(&model.particle_texture);
-    draw.to_frame(app, &frame).unwrap();
-    model.egui.draw_to_frame(&frame).unwrap();
 }
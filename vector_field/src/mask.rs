@@ -0,0 +1,61 @@
+/*
+MIT License
+
+Copyright (c) 2023 Vincent Hiribarren
+
+Permission is hereby granted, free of charge, to any person obtaining a copy
+of this software and associated documentation files (the "Software"), to deal
+in the Software without restriction, including without limitation the rights
+to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+copies of the Software, and to permit persons to whom the Software is
+furnished to do so, subject to the following conditions:
+
+The above copyright notice and this permission notice shall be included in all
+copies or substantial portions of the Software.
+
+THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+SOFTWARE.
+*/
+
+use std::sync::Arc;
+
+use nannou::{
+    geom::{Rect, Vec2},
+    image::GrayImage,
+};
+
+/// Shape the field is confined to, given by a grayscale image stretched over the container.
+#[derive(Clone, Debug)]
+pub struct Mask {
+    image: Arc<GrayImage>,
+    /// Lowest pixel value inside the shape.
+    pub threshold: u8,
+}
+
+impl Mask {
+    pub fn new(image: GrayImage, threshold: u8) -> Self {
+        Mask {
+            image: Arc::new(image),
+            threshold,
+        }
+    }
+
+    /// Whether `position`, a position in `container`, lies inside the shape. Positions out of
+    /// the container lie outside.
+    pub fn contains(&self, container: Rect, position: Vec2) -> bool {
+        if !container.contains(position) || self.image.width() == 0 || self.image.height() == 0 {
+            return false;
+        }
+        // Image rows go from the top down.
+        let u = (position.x - container.left()) / container.w();
+        let v = (container.top() - position.y) / container.h();
+        let column = ((u * self.image.width() as f32) as u32).min(self.image.width() - 1);
+        let row = ((v * self.image.height() as f32) as u32).min(self.image.height() - 1);
+        self.image.get_pixel(column, row).0[0] >= self.threshold
+    }
+}
@@ -0,0 +1,172 @@
+/*
+MIT License
+
+Copyright (c) 2023 Vincent Hiribarren
+
+Permission is hereby granted, free of charge, to any person obtaining a copy
+of this software and associated documentation files (the "Software"), to deal
+in the Software without restriction, including without limitation the rights
+to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+copies of the Software, and to permit persons to whom the Software is
+furnished to do so, subject to the following conditions:
+
+The above copyright notice and this permission notice shall be included in all
+copies or substantial portions of the Software.
+
+THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+SOFTWARE.
+*/
+
+use std::{f64::consts::TAU, sync::Arc};
+
+use nannou::noise::NoiseFn;
+
+/// Noise function the field can be sampled from, in 2 or 4 dimensions.
+pub trait FieldNoise: NoiseFn<[f64; 2]> + NoiseFn<[f64; 4]> + Send + Sync {}
+
+impl<N: NoiseFn<[f64; 2]> + NoiseFn<[f64; 4]> + Send + Sync> FieldNoise for N {}
+
+/// Noise function shareable between threads.
+pub type SharedNoise = Arc<dyn FieldNoise>;
+
+/// Number of dimensions the field noise is sampled in.
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum NoiseDimensions {
+    /// Only `x` and `y`, giving a still field cheaper to sample, whatever the time and depth.
+    Two,
+    /// `x` and `y`, then the depth and the time.
+    Four,
+}
+
+/// Linear interpolation between two noise functions.
+pub struct Crossfade {
+    pub from: SharedNoise,
+    pub to: SharedNoise,
+    /// Weight of `to`, from 0 to 1.
+    pub mix: f64,
+}
+
+impl NoiseFn<[f64; 2]> for Crossfade {
+    fn get(&self, point: [f64; 2]) -> f64 {
+        let from = self.from.get(point);
+        from + (self.to.get(point) - from) * self.mix
+    }
+}
+
+impl NoiseFn<[f64; 4]> for Crossfade {
+    fn get(&self, point: [f64; 4]) -> f64 {
+        let from = self.from.get(point);
+        from + (self.to.get(point) - from) * self.mix
+    }
+}
+
+/// Maps a noise function from `[-1, max]` to the `[-1, 1]` range of the other noise functions,
+/// clamping what exceeds `max`.
+pub struct Rescaled<N> {
+    pub noise: N,
+    pub max: f64,
+}
+
+impl<N: NoiseFn<[f64; D]>, const D: usize> NoiseFn<[f64; D]> for Rescaled<N> {
+    fn get(&self, point: [f64; D]) -> f64 {
+        let value = (self.noise.get(point) + 1.0) / (self.max + 1.0) * 2.0 - 1.0;
+        value.clamp(-1.0, 1.0)
+    }
+}
+
+/// Fractal Brownian motion parameters, layering several octaves of a noise function.
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub struct Fbm {
+    pub octaves: usize,
+    /// Frequency multiplier between successive octaves.
+    pub lacunarity: f32,
+    /// Amplitude multiplier between successive octaves.
+    pub persistence: f32,
+    /// Samples the noise on a torus, so that the field repeats every unit along `x` and `y`.
+    pub seamless: bool,
+}
+
+impl Default for Fbm {
+    fn default() -> Self {
+        Fbm {
+            octaves: 1,
+            lacunarity: 2.0,
+            persistence: 0.5,
+            seamless: false,
+        }
+    }
+}
+
+impl Fbm {
+    /// Sums the octaves of `noise` at `[x, y, z, w]`, octave `i` being sampled at
+    /// `frequency * lacunarity^i` with a weight of `persistence^i`.
+    ///
+    /// Only `x` and `y` are scaled by the frequency, `z` selecting a slice of the noise and `w`
+    /// being the time.
+    ///
+    /// The sum is normalized back to the `[-1, 1]` range of a single octave, so that a single
+    /// octave gives exactly the value of `noise`.
+    ///
+    /// When seamless, `x` and `y` are mapped around two circles spanning the 4 dimensions of the
+    /// noise, of circumference `frequency`, `z` and `w` offsetting them.
+    pub fn sample<N: NoiseFn<[f64; 4]> + ?Sized>(
+        &self,
+        noise: &N,
+        x: f32,
+        y: f32,
+        z: f64,
+        w: f64,
+        frequency: f32,
+    ) -> f64 {
+        let mut sum = 0.0;
+        let mut amplitude_sum = 0.0;
+        let mut amplitude = 1.0;
+        let mut frequency = frequency;
+        for _ in 0..self.octaves.max(1) {
+            let point = if self.seamless {
+                let radius = frequency as f64 / TAU;
+                let (u, v) = (x as f64 * TAU, y as f64 * TAU);
+                [
+                    radius * u.cos(),
+                    radius * u.sin(),
+                    radius * v.cos() + z,
+                    radius * v.sin() + w,
+                ]
+            } else {
+                [(x * frequency) as f64, (y * frequency) as f64, z, w]
+            };
+            sum += amplitude * noise.get(point);
+            amplitude_sum += amplitude;
+            amplitude *= self.persistence as f64;
+            frequency *= self.lacunarity;
+        }
+        sum / amplitude_sum
+    }
+
+    /// Sums the octaves of `noise` at `[x, y]` as `sample` does, without the depth and time
+    /// coordinates. The noise cannot be seamless with only 2 dimensions.
+    pub fn sample_2d<N: NoiseFn<[f64; 2]> + ?Sized>(
+        &self,
+        noise: &N,
+        x: f32,
+        y: f32,
+        frequency: f32,
+    ) -> f64 {
+        let mut sum = 0.0;
+        let mut amplitude_sum = 0.0;
+        let mut amplitude = 1.0;
+        let mut frequency = frequency;
+        for _ in 0..self.octaves.max(1) {
+            sum += amplitude * noise.get([(x * frequency) as f64, (y * frequency) as f64]);
+            amplitude_sum += amplitude;
+            amplitude *= self.persistence as f64;
+            frequency *= self.lacunarity;
+        }
+        sum / amplitude_sum
+    }
+}
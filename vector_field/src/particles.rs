@@ -22,16 +22,189 @@ OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 SOFTWARE.
 */
 
-use nannou::Draw;
+use std::sync::Arc;
+
+use nannou::{
+    color::Srgb,
+    geom::{Rect, Vec2},
+    prelude::TAU,
+    rand::Rng,
+    wgpu, Draw,
+};
 use nannou_egui::egui::Ui;
+use serde::{Deserialize, Serialize};
 
-use crate::Radian;
+use crate::{
+    field::{FieldParams, FieldSource},
+    mask::Mask,
+};
+use simple::{
+    BoundaryMode, CullAction, DrawOrder, EmitterConfig, Integration, ParticleColorMode,
+    ParticleShape,
+};
 
+pub mod gpu;
 pub mod simple;
 
+/// Count beyond which `ParticleSystem::spawn_at` spawns no more particles.
+pub const SPAWN_COUNT_MAX: usize = 1_000_000;
+/// Radius of the cluster spawned by `ParticleSystem::spawn_at`, in pixels.
+const SPAWN_RADIUS: f32 = 20.0;
+
+/// Implementations of `ParticleSystem` available in the GUI.
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum ParticleBackend {
+    Cpu,
+    Gpu,
+}
+
+/// Builds the particle system of the given backend, its particles drawn from `seed`.
+pub fn build_particle_system(
+    backend: ParticleBackend,
+    container: Rect,
+    seed: u64,
+    device_queue_pair: &Arc<wgpu::DeviceQueuePair>,
+) -> Box<dyn ParticleSystem> {
+    let mut particle_system: Box<dyn ParticleSystem> = match backend {
+        ParticleBackend::Cpu => Box::new(simple::SimpleParticleSystem::new(container)),
+        ParticleBackend::Gpu => Box::new(gpu::GpuParticleSystem::new(
+            container,
+            device_queue_pair.clone(),
+        )),
+    };
+    particle_system.set_seed(seed);
+    particle_system.reset();
+    particle_system
+}
+
+/// `count` random positions spread evenly over the disc of `SPAWN_RADIUS` around `center`.
+fn spawn_positions(rng: &mut impl Rng, center: Vec2, count: usize) -> Vec<Vec2> {
+    (0..count)
+        .map(|_| {
+            let radius = SPAWN_RADIUS * rng.gen::<f32>().sqrt();
+            center + Vec2::new(radius, 0.0).rotate(rng.gen::<f32>() * TAU)
+        })
+        .collect()
+}
+
+/// Tunable parameters of a particle system, as stored in the settings file.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+#[serde(default)]
+pub struct ParticleSettings {
+    pub count: usize,
+    pub move_delta: f32,
+    pub size: f32,
+    /// Number of past positions drawn behind each particle, 0 drawing none.
+    pub trail_len: usize,
+    pub shape: ParticleShape,
+    pub draw_order: DrawOrder,
+    pub color_mode: ParticleColorMode,
+    pub boundary_mode: BoundaryMode,
+    pub integration: Integration,
+    /// Fraction of the gap to the field velocity closed at each update, from 0 to 1.
+    pub steering: f32,
+    pub damping: f32,
+    pub cull_offscreen: bool,
+    /// In pixels.
+    pub cull_margin: f32,
+    pub cull_action: CullAction,
+    pub limit_age: bool,
+    /// In seconds.
+    pub max_age: f32,
+    pub age_jitter: f32,
+    pub emitting: bool,
+    pub emitter: EmitterConfig,
+}
+
+impl Default for ParticleSettings {
+    fn default() -> Self {
+        ParticleSettings {
+            count: simple::PARTICLE_COUNT_DEFAULT,
+            move_delta: simple::PARTICLE_MOVE_DELTA,
+            size: simple::PARTICLE_SIZE_DEFAULT,
+            trail_len: 0,
+            shape: ParticleShape::Square,
+            draw_order: DrawOrder::Spawn,
+            color_mode: ParticleColorMode::Random,
+            boundary_mode: BoundaryMode::Respawn,
+            integration: Integration::Euler,
+            steering: simple::STEERING_DEFAULT,
+            damping: simple::DAMPING_DEFAULT,
+            cull_offscreen: false,
+            cull_margin: simple::CULL_MARGIN_DEFAULT,
+            cull_action: CullAction::Skip,
+            limit_age: false,
+            max_age: simple::MAX_AGE_DEFAULT,
+            age_jitter: simple::AGE_JITTER_DEFAULT,
+            emitting: false,
+            emitter: EmitterConfig::default(),
+        }
+    }
+}
+
+/// Snapshot of a single particle, as returned by point queries.
+#[derive(Clone, Copy, Debug)]
+pub struct ParticleInfo {
+    pub position: Vec2,
+    /// Velocity during the last update, in pixels per second.
+    pub velocity: Vec2,
+    /// Seconds since the particle was spawned.
+    pub age: f32,
+}
+
+/// Radial push applied to the particles around a point, e.g. the dragged mouse.
+#[derive(Clone, Copy, Debug)]
+pub struct ForceSource {
+    pub position: Vec2,
+    /// Distance beyond which particles are not pushed, in pixels.
+    pub radius: f32,
+    /// Impulse given to particles at `position`, in pixels per second, fading to 0 at `radius`.
+    pub strength: f32,
+}
+
 pub trait ParticleSystem {
+    /// Spawns the particles again, drawn from the seed so that the layout is reproducible.
     fn reset(&mut self);
-    fn update(&mut self, noise_z: f32, frequency: f32, max_angle: Radian);
+    /// Advances the particles by `dt` seconds along `field`, pushed away from `forces`.
+    fn update(
+        &mut self,
+        field: &dyn FieldSource,
+        params: &FieldParams,
+        forces: &[ForceSource],
+        dt: f32,
+    );
     fn draw(&self, draw: &Draw);
     fn config_gui(&mut self, ui: &mut Ui);
+    /// Returns the particle closest to `point`, if any.
+    fn nearest(&self, point: Vec2) -> Option<ParticleInfo>;
+    /// Positions of the particles, empty when they cannot be read back.
+    fn positions(&self) -> Box<dyn Iterator<Item = Vec2> + '_>;
+    /// Positions and colors of the particles, as taken by `load_positions`, empty when they
+    /// cannot be read back.
+    fn snapshot(&self) -> Vec<(Vec2, Srgb<u8>)>;
+    fn config(&self) -> ParticleSettings;
+    /// Applies the settings, keeping the existing particles.
+    fn apply_config(&mut self, settings: &ParticleSettings);
+    /// Restores the default settings.
+    fn reset_config(&mut self) {
+        self.apply_config(&ParticleSettings::default());
+    }
+    fn count(&self) -> usize;
+    /// Spawns or removes particles to reach `count`, leaving the others untouched.
+    fn set_count(&mut self, count: usize);
+    /// Spawns `count` particles clustered around `center`, stopping at `SPAWN_COUNT_MAX`
+    /// particles.
+    fn spawn_at(&mut self, center: Vec2, count: usize);
+    /// Replaces the particles by ones at the given positions, clamped into the container, with
+    /// the given colors.
+    fn load_positions(&mut self, particles: Vec<(Vec2, Srgb<u8>)>);
+    /// Sets the seed of the next `reset`.
+    fn set_seed(&mut self, seed: u64);
+    /// Replaces the rect the particles move in, e.g. when the window is resized.
+    fn set_container(&mut self, container: Rect);
+    /// Confines the particles to `mask`, respawning those which leave it, or frees them.
+    fn set_mask(&mut self, mask: Option<Mask>);
+    /// Identifies the field given to the next updates besides its `FieldParams`, for the
+    /// systems which keep a copy of it to know when it changed.
+    fn set_field_version(&mut self, _version: u64) {}
 }
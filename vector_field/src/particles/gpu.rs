@@ -0,0 +1,498 @@
+/*
+MIT License
+
+Copyright (c) 2023 Vincent Hiribarren
+
+Permission is hereby granted, free of charge, to any person obtaining a copy
+of this software and associated documentation files (the "Software"), to deal
+in the Software without restriction, including without limitation the rights
+to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+copies of the Software, and to permit persons to whom the Software is
+furnished to do so, subject to the following conditions:
+
+The above copyright notice and this permission notice shall be included in all
+copies or substantial portions of the Software.
+
+THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+SOFTWARE.
+*/
+
+//! Particle system advected and drawn on the GPU.
+//!
+//! The field angle is sampled on the CPU over a coarse grid, uploaded as a texture and looked up
+//! by a compute shader moving the particles. The particles are then rendered into their own
+//! texture, which `draw` composites.
+
+use std::{iter, mem, num::NonZeroU32, sync::Arc};
+
+use nannou::{
+    prelude::*,
+    rand::{rngs::StdRng, Rng, SeedableRng},
+    wgpu::{self, util::DeviceExt},
+};
+use nannou_egui::egui;
+
+use crate::{
+    field::{field_angle, FieldParams, FieldSource},
+    mask::Mask,
+};
+
+use super::{
+    simple::{PARTICLE_COUNT_DEFAULT, PARTICLE_MOVE_DELTA, PARTICLE_SIZE_DEFAULT},
+    spawn_positions, ForceSource, ParticleInfo, ParticleSettings, ParticleSystem, SPAWN_COUNT_MAX,
+};
+
+/// Number of field samples along each side of the container.
+const FIELD_RESOLUTION: u32 = 128;
+/// Must match the `workgroup_size` of the advection shader.
+const WORKGROUP_SIZE: u32 = 64;
+
+/// A particle as stored on the GPU: position in xy, velocity in zw.
+type GpuParticle = [f32; 4];
+
+/// Must match the `Params` struct of the shaders.
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct Params {
+    container: [f32; 4],
+    move_delta: f32,
+    dt: f32,
+    size: f32,
+    count: u32,
+    field_resolution: u32,
+    _padding: [u32; 3],
+}
+
+pub struct GpuParticleSystem {
+    device_queue_pair: Arc<wgpu::DeviceQueuePair>,
+    container: Rect,
+    count: usize,
+    seed: u64,
+    /// Draws the spawned particles, seeded again by `reset` and `set_seed` so that a run is
+    /// reproducible.
+    rng: StdRng,
+    move_delta: f32,
+    size: f32,
+    particles: wgpu::Buffer,
+    params: wgpu::Buffer,
+    field: wgpu::Texture,
+    field_version: u64,
+    /// Parameters and version of the field last uploaded to `field`.
+    uploaded_field: Option<(FieldParams, u64)>,
+    advect_layout: wgpu::BindGroupLayout,
+    advect_bind_group: wgpu::BindGroup,
+    advect_pipeline: wgpu::ComputePipeline,
+    draw_bind_group: wgpu::BindGroup,
+    draw_pipeline: wgpu::RenderPipeline,
+    texture: wgpu::Texture,
+    texture_view: wgpu::TextureView,
+}
+
+impl GpuParticleSystem {
+    pub fn new(container: Rect, device_queue_pair: Arc<wgpu::DeviceQueuePair>) -> Self {
+        let device = device_queue_pair.device();
+        let count = PARTICLE_COUNT_DEFAULT;
+        let mut rng = StdRng::seed_from_u64(0);
+        let particles = particle_buffer(device, &random_particles(&mut rng, container, count));
+        let params = device.create_buffer_init(&wgpu::BufferInitDescriptor {
+            label: Some("particle params"),
+            contents: &[0; mem::size_of::<Params>()],
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let field = wgpu::TextureBuilder::new()
+            .size([FIELD_RESOLUTION, FIELD_RESOLUTION])
+            .usage(wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST)
+            .format(wgpu::TextureFormat::R32Float)
+            .build(device);
+        let texture = output_texture(device, container);
+        let texture_view = texture.view().build();
+
+        let advect_module =
+            device.create_shader_module(&wgpu::include_wgsl!("shaders/advect.wgsl"));
+        let advect_layout = wgpu::BindGroupLayoutBuilder::new()
+            .uniform_buffer(wgpu::ShaderStages::COMPUTE, false)
+            .storage_buffer(wgpu::ShaderStages::COMPUTE, false, false)
+            .texture(
+                wgpu::ShaderStages::COMPUTE,
+                false,
+                wgpu::TextureViewDimension::D2,
+                wgpu::TextureSampleType::Float { filterable: false },
+            )
+            .build(device);
+        let advect_bind_group =
+            advect_bind_group(device, &advect_layout, &params, &particles, &field);
+        let advect_pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("advect particles"),
+            layout: Some(&pipeline_layout(device, &advect_layout)),
+            module: &advect_module,
+            entry_point: "main",
+        });
+
+        let draw_module = device.create_shader_module(&wgpu::include_wgsl!("shaders/draw.wgsl"));
+        let draw_layout = wgpu::BindGroupLayoutBuilder::new()
+            .uniform_buffer(wgpu::ShaderStages::VERTEX, false)
+            .build(device);
+        let draw_bind_group = wgpu::BindGroupBuilder::new()
+            .buffer::<Params>(&params, 0..1)
+            .build(device, &draw_layout);
+        let draw_pipeline = wgpu::RenderPipelineBuilder::from_layout(
+            &pipeline_layout(device, &draw_layout),
+            &draw_module,
+        )
+        .vertex_entry_point("vs_main")
+        .fragment_shader(&draw_module)
+        .fragment_entry_point("fs_main")
+        .color_format(texture.format())
+        .add_instance_buffer::<GpuParticle>(&wgpu::vertex_attr_array![0 => Float32x4])
+        .build(device);
+
+        GpuParticleSystem {
+            device_queue_pair,
+            container,
+            count,
+            seed: 0,
+            rng,
+            move_delta: PARTICLE_MOVE_DELTA,
+            size: PARTICLE_SIZE_DEFAULT,
+            particles,
+            params,
+            field,
+            advect_layout,
+            advect_bind_group,
+            advect_pipeline,
+            draw_bind_group,
+            draw_pipeline,
+            texture,
+            texture_view,
+            field_version: 0,
+            uploaded_field: None,
+        }
+    }
+
+    /// Replaces the particle buffer, which invalidates the bind group referencing it.
+    fn set_particles(&mut self, particles: wgpu::Buffer) {
+        let device = self.device_queue_pair.device();
+        self.advect_bind_group = advect_bind_group(
+            device,
+            &self.advect_layout,
+            &self.params,
+            &particles,
+            &self.field,
+        );
+        self.particles = particles;
+    }
+
+    /// Samples the field angles over the container and uploads them to the field texture.
+    fn upload_field(&self, field: &dyn FieldSource, params: &FieldParams) {
+        let last = (FIELD_RESOLUTION - 1) as f32;
+        let mut angles = Vec::with_capacity((FIELD_RESOLUTION * FIELD_RESOLUTION) as usize);
+        for j in 0..FIELD_RESOLUTION {
+            for i in 0..FIELD_RESOLUTION {
+                // The shader addresses the texture in normalized coordinates of the default
+                // orientation, unzoomed, so each texel samples the field at its position.
+                let position = Vec2::new(
+                    self.container.right() - i as f32 / last * self.container.w(),
+                    self.container.top() - j as f32 / last * self.container.h(),
+                );
+                angles.push(field_angle(field, self.container, position, params));
+            }
+        }
+        self.device_queue_pair.queue().write_texture(
+            wgpu::ImageCopyTexture {
+                texture: &self.field,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+                aspect: wgpu::TextureAspect::All,
+            },
+            unsafe { wgpu::bytes::from_slice(&angles) },
+            wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: NonZeroU32::new(4 * FIELD_RESOLUTION),
+                rows_per_image: NonZeroU32::new(FIELD_RESOLUTION),
+            },
+            self.field.extent(),
+        );
+    }
+}
+
+fn random_particles(rng: &mut impl Rng, container: Rect, count: usize) -> Vec<GpuParticle> {
+    (0..count)
+        .map(|_| {
+            [
+                container.left() + rng.gen::<f32>() * container.w(),
+                container.bottom() + rng.gen::<f32>() * container.h(),
+                0.0,
+                0.0,
+            ]
+        })
+        .collect()
+}
+
+/// Texture the particles are drawn into, covering `container`.
+fn output_texture(device: &wgpu::Device, container: Rect) -> wgpu::Texture {
+    wgpu::TextureBuilder::new()
+        .size([container.w() as u32, container.h() as u32])
+        .usage(wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING)
+        .format(wgpu::TextureFormat::Rgba16Float)
+        .build(device)
+}
+
+/// A buffer holding at least one particle, as empty bindings are invalid.
+fn particle_buffer(device: &wgpu::Device, particles: &[GpuParticle]) -> wgpu::Buffer {
+    let placeholder = [[0.0; 4]];
+    let particles = if particles.is_empty() {
+        &placeholder[..]
+    } else {
+        particles
+    };
+    device.create_buffer_init(&wgpu::BufferInitDescriptor {
+        label: Some("particles"),
+        contents: unsafe { wgpu::bytes::from_slice(particles) },
+        usage: wgpu::BufferUsages::STORAGE
+            | wgpu::BufferUsages::VERTEX
+            | wgpu::BufferUsages::COPY_SRC
+            | wgpu::BufferUsages::COPY_DST,
+    })
+}
+
+fn advect_bind_group(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    params: &wgpu::Buffer,
+    particles: &wgpu::Buffer,
+    field: &wgpu::Texture,
+) -> wgpu::BindGroup {
+    wgpu::BindGroupBuilder::new()
+        .buffer::<Params>(params, 0..1)
+        .buffer_bytes(particles, 0, None)
+        .texture_view(&field.view().build())
+        .build(device, layout)
+}
+
+fn pipeline_layout(device: &wgpu::Device, layout: &wgpu::BindGroupLayout) -> wgpu::PipelineLayout {
+    device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+        label: Some("gpu particles"),
+        bind_group_layouts: &[layout],
+        push_constant_ranges: &[],
+    })
+}
+
+impl ParticleSystem for GpuParticleSystem {
+    fn reset(&mut self) {
+        let device = self.device_queue_pair.device();
+        self.rng = StdRng::seed_from_u64(self.seed);
+        let particles = particle_buffer(
+            device,
+            &random_particles(&mut self.rng, self.container, self.count),
+        );
+        self.set_particles(particles);
+    }
+    /// The shader does not apply the force sources.
+    fn update(
+        &mut self,
+        field: &dyn FieldSource,
+        field_params: &FieldParams,
+        _forces: &[ForceSource],
+        dt: f32,
+    ) {
+        // Substeps share the field, uploaded only once.
+        let field_key = (*field_params, self.field_version);
+        if self.uploaded_field != Some(field_key) {
+            self.upload_field(field, field_params);
+            self.uploaded_field = Some(field_key);
+        }
+        let params = Params {
+            container: [
+                self.container.left(),
+                self.container.bottom(),
+                self.container.w(),
+                self.container.h(),
+            ],
+            move_delta: self.move_delta,
+            dt,
+            size: self.size,
+            count: self.count as u32,
+            field_resolution: FIELD_RESOLUTION,
+            _padding: [0; 3],
+        };
+        let queue = self.device_queue_pair.queue();
+        queue.write_buffer(&self.params, 0, unsafe { wgpu::bytes::from(&params) });
+
+        let device = self.device_queue_pair.device();
+        let ce_desc = wgpu::CommandEncoderDescriptor {
+            label: Some("gpu particles"),
+        };
+        let mut encoder = device.create_command_encoder(&ce_desc);
+        {
+            let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("advect particles"),
+            });
+            compute_pass.set_pipeline(&self.advect_pipeline);
+            compute_pass.set_bind_group(0, &self.advect_bind_group, &[]);
+            let workgroups = (self.count as u32 + WORKGROUP_SIZE - 1) / WORKGROUP_SIZE;
+            compute_pass.dispatch(workgroups, 1, 1);
+        }
+        {
+            let mut render_pass = wgpu::RenderPassBuilder::new()
+                .color_attachment(&self.texture_view, |color| {
+                    color.load_op(wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT))
+                })
+                .begin(&mut encoder);
+            render_pass.set_pipeline(&self.draw_pipeline);
+            render_pass.set_bind_group(0, &self.draw_bind_group, &[]);
+            render_pass.set_vertex_buffer(0, self.particles.slice(..));
+            render_pass.draw(0..6, 0..self.count as u32);
+        }
+        queue.submit(Some(encoder.finish()));
+    }
+    fn draw(&self, draw: &Draw) {
+        draw.texture(&self.texture)
+            .xy(self.container.xy())
+            .wh(self.container.wh());
+    }
+    fn config_gui(&mut self, ui: &mut egui::Ui) {
+        ui.vertical(|ui| {
+            ui.horizontal(|ui| {
+                // `set_count` needs the current count to know which particles to keep.
+                let mut count = self.count;
+                if ui
+                    .add(egui::DragValue::new(&mut count).speed(1000))
+                    .changed()
+                {
+                    self.set_count(count);
+                }
+                ui.label("particles");
+            });
+            ui.horizontal(|ui| {
+                ui.add(egui::DragValue::new(&mut self.move_delta));
+                ui.label("move delta (px/s)");
+            });
+            ui.horizontal(|ui| {
+                ui.add(egui::DragValue::new(&mut self.size).clamp_range(0.0..=100.0));
+                ui.label("size");
+            });
+            ui.label("Particles wrap around the edges.");
+        });
+    }
+    /// Particles only live on the GPU, so they cannot be queried.
+    fn nearest(&self, _point: Vec2) -> Option<ParticleInfo> {
+        None
+    }
+    /// Particles only live on the GPU, so they cannot be read back.
+    fn positions(&self) -> Box<dyn Iterator<Item = Vec2> + '_> {
+        Box::new(iter::empty())
+    }
+    fn snapshot(&self) -> Vec<(Vec2, rgb::Srgb<u8>)> {
+        Vec::new()
+    }
+    fn config(&self) -> ParticleSettings {
+        ParticleSettings {
+            count: self.count,
+            move_delta: self.move_delta,
+            size: self.size,
+            ..ParticleSettings::default()
+        }
+    }
+    /// Only the count, speed and size are supported, the other settings are ignored.
+    fn apply_config(&mut self, settings: &ParticleSettings) {
+        self.move_delta = settings.move_delta;
+        self.size = settings.size.clamp(0.0, 100.0);
+        self.set_count(settings.count);
+    }
+    fn count(&self) -> usize {
+        self.count
+    }
+    fn set_count(&mut self, count: usize) {
+        if count == self.count {
+            return;
+        }
+        let device = self.device_queue_pair.device();
+        let kept = count.min(self.count);
+        let particles = particle_buffer(device, &vec![[0.0; 4]; count]);
+        let ce_desc = wgpu::CommandEncoderDescriptor {
+            label: Some("resize particles"),
+        };
+        let mut encoder = device.create_command_encoder(&ce_desc);
+        let particle_size = mem::size_of::<GpuParticle>() as wgpu::BufferAddress;
+        if kept > 0 {
+            encoder.copy_buffer_to_buffer(
+                &self.particles,
+                0,
+                &particles,
+                0,
+                kept as wgpu::BufferAddress * particle_size,
+            );
+        }
+        let queue = self.device_queue_pair.queue();
+        queue.submit(Some(encoder.finish()));
+        if count > kept {
+            let spawned = random_particles(&mut self.rng, self.container, count - kept);
+            queue.write_buffer(
+                &particles,
+                kept as wgpu::BufferAddress * particle_size,
+                unsafe { wgpu::bytes::from_slice(&spawned) },
+            );
+        }
+        self.count = count;
+        self.set_particles(particles);
+    }
+    fn spawn_at(&mut self, center: Vec2, count: usize) {
+        let kept = self.count;
+        let count = count.min(SPAWN_COUNT_MAX.saturating_sub(kept));
+        if count == 0 {
+            return;
+        }
+        self.set_count(kept + count);
+        let spawned: Vec<GpuParticle> = spawn_positions(&mut self.rng, center, count)
+            .into_iter()
+            .map(|position| [position.x, position.y, 0.0, 0.0])
+            .collect();
+        // Overwrites the random particles `set_count` spawned.
+        self.device_queue_pair.queue().write_buffer(
+            &self.particles,
+            kept as wgpu::BufferAddress * mem::size_of::<GpuParticle>() as wgpu::BufferAddress,
+            unsafe { wgpu::bytes::from_slice(&spawned) },
+        );
+    }
+    /// Colors are derived from the particle index on the GPU, so the given ones are ignored.
+    fn load_positions(&mut self, particles: Vec<(Vec2, rgb::Srgb<u8>)>) {
+        let container = self.container;
+        let particles: Vec<GpuParticle> = particles
+            .iter()
+            .map(|(position, _)| {
+                [
+                    position.x.clamp(container.left(), container.right()),
+                    position.y.clamp(container.bottom(), container.top()),
+                    0.0,
+                    0.0,
+                ]
+            })
+            .collect();
+        self.count = particles.len();
+        let particles = particle_buffer(self.device_queue_pair.device(), &particles);
+        self.set_particles(particles);
+    }
+    fn set_seed(&mut self, seed: u64) {
+        self.seed = seed;
+        self.rng = StdRng::seed_from_u64(seed);
+    }
+    /// Particles only live on the GPU, so they are spawned again over the new container.
+    fn set_container(&mut self, container: Rect) {
+        self.container = container;
+        // Sampled over the previous container.
+        self.uploaded_field = None;
+        self.texture = output_texture(self.device_queue_pair.device(), container);
+        self.texture_view = self.texture.view().build();
+        self.reset();
+    }
+    /// Masks are not supported, the particles are left free.
+    fn set_mask(&mut self, _mask: Option<Mask>) {}
+    fn set_field_version(&mut self, version: u64) {
+        self.field_version = version;
+    }
+}
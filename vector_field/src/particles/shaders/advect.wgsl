@@ -0,0 +1,48 @@
+// Moves each particle along the field angle sampled from the field texture.
+
+[[block]]
+struct Params {
+    // Left, bottom, width and height of the container.
+    container: vec4<f32>;
+    move_delta: f32;
+    dt: f32;
+    size: f32;
+    count: u32;
+    field_resolution: u32;
+};
+
+[[block]]
+struct Particles {
+    // Position in xy, velocity in zw.
+    data: array<vec4<f32>>;
+};
+
+[[group(0), binding(0)]] var<uniform> params: Params;
+[[group(0), binding(1)]] var<storage, read_write> particles: Particles;
+[[group(0), binding(2)]] var field: texture_2d<f32>;
+
+[[stage(compute), workgroup_size(64)]]
+fn main([[builtin(global_invocation_id)]] id: vec3<u32>) {
+    let index = id.x;
+    if (index >= params.count) {
+        return;
+    }
+    let origin = params.container.xy;
+    let size = params.container.zw;
+    let particle = particles.data[index];
+
+    // Same normalized coordinates as the CPU implementation, growing toward the left and the
+    // bottom of the container.
+    let normalized = vec2<f32>(1.0, 1.0) - (particle.xy - origin) / size;
+    let last = f32(params.field_resolution - 1u);
+    let texel = vec2<i32>(
+        i32(clamp(floor(normalized.x * last + 0.5), 0.0, last)),
+        i32(clamp(floor(normalized.y * last + 0.5), 0.0, last)),
+    );
+    let angle = textureLoad(field, texel, 0).r;
+
+    let velocity = vec2<f32>(cos(angle), sin(angle)) * params.move_delta;
+    let position = particle.xy + velocity * params.dt;
+    let wrapped = origin + ((position - origin) % size + size) % size;
+    particles.data[index] = vec4<f32>(wrapped, velocity);
+}
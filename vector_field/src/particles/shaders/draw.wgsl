@@ -0,0 +1,55 @@
+// Draws each particle as a square, with a color derived from its index.
+
+[[block]]
+struct Params {
+    // Left, bottom, width and height of the container.
+    container: vec4<f32>;
+    move_delta: f32;
+    dt: f32;
+    size: f32;
+    count: u32;
+    field_resolution: u32;
+};
+
+[[group(0), binding(0)]] var<uniform> params: Params;
+
+struct VertexOutput {
+    [[builtin(position)]] position: vec4<f32>;
+    [[location(0)]] color: vec4<f32>;
+};
+
+fn hash(value: u32) -> f32 {
+    var x = value * 747796405u + 2891336453u;
+    x = ((x >> ((x >> 28u) + 4u)) ^ x) * 277803737u;
+    x = (x >> 22u) ^ x;
+    return f32(x) / 4294967295.0;
+}
+
+[[stage(vertex)]]
+fn vs_main(
+    [[builtin(vertex_index)]] vertex_index: u32,
+    [[builtin(instance_index)]] instance_index: u32,
+    [[location(0)]] particle: vec4<f32>,
+) -> VertexOutput {
+    var corners = array<vec2<f32>, 6>(
+        vec2<f32>(-0.5, -0.5),
+        vec2<f32>(0.5, -0.5),
+        vec2<f32>(0.5, 0.5),
+        vec2<f32>(-0.5, -0.5),
+        vec2<f32>(0.5, 0.5),
+        vec2<f32>(-0.5, 0.5),
+    );
+    let position = particle.xy + corners[vertex_index] * params.size;
+    let clip = (position - params.container.xy) / params.container.zw * 2.0 - vec2<f32>(1.0, 1.0);
+
+    var out: VertexOutput;
+    out.position = vec4<f32>(clip, 0.0, 1.0);
+    let seed = instance_index * 3u;
+    out.color = vec4<f32>(hash(seed), hash(seed + 1u), hash(seed + 2u), 1.0);
+    return out;
+}
+
+[[stage(fragment)]]
+fn fs_main(in: VertexOutput) -> [[location(0)]] vec4<f32> {
+    return in.color;
+}
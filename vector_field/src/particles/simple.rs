@@ -22,102 +22,1200 @@ OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 SOFTWARE.
 */
 
-use std::rc::Rc;
+use std::{
+    collections::{HashMap, VecDeque},
+    iter, mem,
+    sync::Arc,
+};
 
-use nannou::{noise::NoiseFn, prelude::*, rand::random_range};
+use nannou::{
+    color::{Hsv, Hue, IntoLinSrgba, LinSrgb},
+    noise::{NoiseFn, Perlin, Seedable},
+    prelude::*,
+    rand::{rngs::StdRng, Rng, SeedableRng},
+};
 use nannou_egui::egui;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
 
-use crate::Radian;
+use crate::{
+    colormap::Colormap,
+    field::{field_angle, normalized_position, FieldParams, FieldSource},
+    mask::Mask,
+    noise::SharedNoise,
+    Radian,
+};
 
-use super::ParticleSystem;
+use super::{
+    spawn_positions, ForceSource, ParticleInfo, ParticleSettings, ParticleSystem, SPAWN_COUNT_MAX,
+};
 
-const PARTICLE_COUNT_DEFAULT: usize = 1_000;
-const PARTICLE_SIZE_DEFAULT: f32 = 1.5;
-const PARTICLE_MOVE_DELTA: f32 = 2.0;
+pub(crate) const PARTICLE_COUNT_DEFAULT: usize = 1_000;
+pub(crate) const PARTICLE_SIZE_DEFAULT: f32 = 1.5;
+/// In pixels per second.
+pub(crate) const PARTICLE_MOVE_DELTA: f32 = 120.0;
+pub(crate) const CULL_MARGIN_DEFAULT: f32 = 50.0;
+/// In seconds.
+pub(crate) const MAX_AGE_DEFAULT: f32 = 8.0;
+pub(crate) const AGE_JITTER_DEFAULT: f32 = 0.5;
+/// Fraction of the gap to the field velocity closed at each update, 1 snapping to the field.
+pub(crate) const STEERING_DEFAULT: f32 = 1.0;
+/// Fraction of the velocity lost at each update.
+pub(crate) const DAMPING_DEFAULT: f32 = 0.0;
+/// Fraction of the lifetime a tapered particle takes to grow, and then to shrink.
+const TAPER_FRACTION: f32 = 0.2;
+/// Longest trail allowed in the GUI, in positions.
+const TRAIL_LEN_MAX: usize = 100;
+/// Most past positions kept over all the particles, the trails being shortened beyond.
+const TRAIL_POINTS_MAX: usize = 1_000_000;
+/// In pixels.
+const NEIGHBOR_RADIUS_DEFAULT: f32 = 20.0;
+/// Number of triangles of a circle particle in the batched mesh.
+const CIRCLE_SEGMENTS: usize = 16;
+/// Mixed into the seed of the speed noise, for it not to follow the field noise.
+const SPEED_NOISE_SEED_SALT: u32 = 0x5eed_5bee;
+/// Finite difference step used to derive the field, in normalized units.
+const GRADIENT_EPSILON: f32 = 1e-3;
+/// In turns per second.
+const HUE_CYCLE_SPEED_DEFAULT: f32 = 0.1;
+/// Random positions tried to respawn a particle inside the mask and away from the crowded
+/// regions, before settling for the last one.
+const SPAWN_ATTEMPTS: usize = 32;
+/// Columns of the density grid biasing the respawns, the rows following the container aspect.
+const DENSITY_COLUMNS: usize = 32;
 
 struct Particle {
     x: f32,
     y: f32,
     color: rgb::Srgb<u8>,
+    /// In pixels per second, steered toward the field at each update.
+    velocity: Vec2,
+    /// Field angle sampled at the particle position during the last update.
+    angle: Radian,
+    /// Seconds since the particle was spawned.
+    age: f32,
+    /// Age at which the particle is respawned, when lifetime is limited.
+    max_age: f32,
+    /// Other particles within the neighbor radius during the last update, only counted when
+    /// coloring by neighbors.
+    neighbors: u32,
+    /// Last positions, the oldest first.
+    trail: VecDeque<Vec2>,
+}
+
+impl Particle {
+    fn state(&self) -> ParticleState {
+        ParticleState {
+            position: Vec2::new(self.x, self.y),
+            velocity: self.velocity,
+        }
+    }
+}
+
+/// Part of a particle the update reads, copied before the update writes the particles.
+#[derive(Clone, Copy)]
+struct ParticleState {
+    position: Vec2,
+    velocity: Vec2,
+}
+
+/// What happens to particles leaving the view rect expanded by the cull margin.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Copy)]
+pub enum CullAction {
+    /// Neither move nor draw them, saving their update. They only age, coming back once
+    /// respawned at the end of a limited life, and otherwise stay where they left the view.
+    Skip,
+    /// Move them back to a random position inside the container.
+    Respawn,
+}
+
+/// Policy applied to particles reaching the container edges.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Copy)]
+pub enum BoundaryMode {
+    /// Let them leave the container.
+    Escape,
+    /// Make them reappear on the opposite edge.
+    Wrap,
+    /// Mirror the step that crossed an edge, keeping them inside.
+    Reflect,
+    /// Move them back to a random position inside the container.
+    Respawn,
+}
+
+/// Order in which particles are drawn, the last ones ending up on top.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Copy)]
+pub enum DrawOrder {
+    /// Storage order, cheapest.
+    Spawn,
+    /// Oldest first.
+    Age,
+    /// Top of the window first.
+    Y,
+}
+
+/// Shape particles are drawn with.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Copy)]
+pub enum ParticleShape {
+    /// Axis-aligned square, cheapest.
+    Square,
+    Circle,
+}
+
+/// How particles move through the field.
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum Behavior {
+    /// Follow the field angle, or its curl.
+    Advect,
+    /// Follow the negative gradient of the angle, settling in its local minima.
+    Descend,
+    /// Follow the gradient of the angle, settling in its local maxima.
+    Ascend,
+}
+
+/// Scheme integrating the particle positions along the field.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Copy)]
+pub enum Integration {
+    /// One sample of the field per update, drifting outward in curved fields.
+    Euler,
+    /// Averages the field at the particle and at the endpoint predicted by Euler.
+    Heun,
+    /// Fourth order Runge-Kutta, four samples of the field per update.
+    Rk4,
+}
+
+/// Grid of points continuously spawning particles, drawing streams along the field.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Copy)]
+#[serde(default)]
+pub struct EmitterConfig {
+    /// Particles spawned by each point per second.
+    pub rate: f32,
+    pub grid_cols: usize,
+    pub grid_rows: usize,
+}
+
+impl Default for EmitterConfig {
+    fn default() -> Self {
+        EmitterConfig {
+            rate: 2.0,
+            grid_cols: 8,
+            grid_rows: 6,
+        }
+    }
+}
+
+/// How particles are colored when drawn.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Copy)]
+pub enum ParticleColorMode {
+    /// Color picked at spawn time.
+    Random,
+    /// Speed relative to `move_delta`, through the colormap.
+    Speed,
+    /// Field angle through the colormap, as in the value grid.
+    Angle,
+    /// Number of neighbors relative to the most surrounded particle, through the colormap.
+    Neighbors,
 }
 
 pub struct SimpleParticleSystem {
     particles: Vec<Particle>,
-    noise: Rc<dyn NoiseFn<[f64; 3]>>,
+    /// States of the particles before the update in progress, kept to reuse the allocation.
+    states: Vec<ParticleState>,
     container: Rect,
     count: usize,
     move_delta: f32,
+    /// Noise scaling `move_delta` by position, independent from the field.
+    speed_noise: SharedNoise,
+    /// Relative spread of the speed around `move_delta` given by `speed_noise`, from 0 to 1.
+    speed_variation: f32,
     default_size: f32,
+    /// Number of past positions drawn behind each particle, 0 drawing none.
+    trail_len: usize,
+    /// Grows the particles after they spawn and shrinks them before they reach their max age.
+    size_taper: bool,
+    shape: ParticleShape,
+    /// Draws all the particles as a single mesh instead of one primitive each.
+    batched: bool,
+    cull_offscreen: bool,
+    cull_margin: f32,
+    cull_action: CullAction,
+    boundary_mode: BoundaryMode,
+    /// Shape the particles are respawned into when they leave it.
+    mask: Option<Mask>,
+    /// Preference of the respawns for the emptier regions, from 0, uniform, to 1.
+    respawn_bias: f32,
+    /// Particles per cell of a grid over the container relative to the fullest cell, updated
+    /// before each update when the respawns are biased.
+    density: Vec<f32>,
+    draw_order: DrawOrder,
+    behavior: Behavior,
+    use_curl: bool,
+    integration: Integration,
+    steering: f32,
+    damping: f32,
+    color_mode: ParticleColorMode,
+    colormap: Colormap,
+    /// Shifts the hue of the particle colors as the simulation runs.
+    hue_cycle: bool,
+    /// In turns per second.
+    hue_cycle_speed: f32,
+    /// Current hue shift, in turns.
+    hue_offset: f32,
+    /// Distance within which particles count as neighbors, in pixels.
+    neighbor_radius: f32,
+    /// Most neighbors of a particle during the last update.
+    neighbors_max: u32,
+    limit_age: bool,
+    max_age: f32,
+    /// Relative spread of the particles max age around `max_age`, from 0 to 1.
+    age_jitter: f32,
+    /// Seed of the generator `reset` draws the particles from.
+    seed: u64,
+    /// Draws the spawned particles, seeded again by `reset` so that a run is reproducible.
+    rng: StdRng,
+    emitting: bool,
+    emitter: EmitterConfig,
+    /// Emissions due but not done yet, in emissions.
+    emitter_lag: f32,
+    /// Index of the next particle replaced by an emission, once the count is reached.
+    emitter_cursor: usize,
 }
 
 impl SimpleParticleSystem {
-    pub fn new(container: Rect, noise: Rc<dyn NoiseFn<[f64; 3]>>) -> Self {
+    pub fn new(container: Rect) -> Self {
         let mut particle_system = Self {
             particles: Vec::with_capacity(PARTICLE_COUNT_DEFAULT),
-            noise,
+            states: Vec::with_capacity(PARTICLE_COUNT_DEFAULT),
             count: PARTICLE_COUNT_DEFAULT,
             move_delta: PARTICLE_MOVE_DELTA,
+            speed_noise: speed_noise(Perlin::DEFAULT_SEED),
+            speed_variation: 0.0,
             default_size: PARTICLE_SIZE_DEFAULT,
+            trail_len: 0,
+            size_taper: false,
+            shape: ParticleShape::Square,
+            batched: true,
+            cull_offscreen: false,
+            cull_margin: CULL_MARGIN_DEFAULT,
+            cull_action: CullAction::Skip,
+            boundary_mode: BoundaryMode::Respawn,
+            mask: None,
+            respawn_bias: 0.0,
+            density: vec![],
+            draw_order: DrawOrder::Spawn,
+            behavior: Behavior::Advect,
+            use_curl: false,
+            integration: Integration::Euler,
+            steering: STEERING_DEFAULT,
+            damping: DAMPING_DEFAULT,
+            color_mode: ParticleColorMode::Random,
+            colormap: Colormap::Turbo,
+            hue_cycle: false,
+            hue_cycle_speed: HUE_CYCLE_SPEED_DEFAULT,
+            hue_offset: 0.0,
+            neighbor_radius: NEIGHBOR_RADIUS_DEFAULT,
+            neighbors_max: 0,
+            limit_age: false,
+            max_age: MAX_AGE_DEFAULT,
+            age_jitter: AGE_JITTER_DEFAULT,
+            seed: 0,
+            rng: StdRng::seed_from_u64(0),
+            emitting: false,
+            emitter: EmitterConfig::default(),
+            emitter_lag: 0.0,
+            emitter_cursor: 0,
             container,
         };
         particle_system.reset();
         particle_system
     }
+
+    /// Factor of `move_delta` at the normalized `perlin` coordinates, around 1.
+    fn speed_factor(&self, perlin: Vec2, params: &FieldParams) -> f32 {
+        if self.speed_variation == 0.0 {
+            return 1.0;
+        }
+        let sample = self.speed_noise.get([
+            (perlin.x * params.frequency) as f64,
+            (perlin.y * params.frequency) as f64,
+            0.0,
+            params.z as f64,
+        ]) as f32;
+        1.0 + self.speed_variation * sample
+    }
+
+    /// Positions kept behind each particle, `trail_len` shortened so that all the trails hold at
+    /// most `TRAIL_POINTS_MAX` positions.
+    fn trail_capacity(&self) -> usize {
+        self.trail_len
+            .min(TRAIL_POINTS_MAX / self.particles.len().max(1))
+    }
+
+    /// Draws the trail of each particle as a polyline fading toward its oldest position.
+    fn draw_trails(&self, draw: &Draw, particles: &[&Particle]) {
+        for particle in particles {
+            if particle.trail.is_empty() {
+                continue;
+            }
+            let color = self.particle_color(particle);
+            let len = particle.trail.len() + 1;
+            let points = particle
+                .trail
+                .iter()
+                .copied()
+                .chain(iter::once(Vec2::new(particle.x, particle.y)))
+                .enumerate()
+                .map(|(index, point)| {
+                    let mut color = color;
+                    color.alpha *= (index + 1) as f32 / len as f32;
+                    (point, color)
+                });
+            draw.polyline()
+                .weight(self.particle_size(particle) / 2.0)
+                .points_colored(points);
+        }
+    }
+
+    /// The container expanded by the cull margin, when culling is enabled.
+    fn cull_rect(&self) -> Option<Rect> {
+        self.cull_offscreen
+            .then(|| self.container.pad(-self.cull_margin))
+    }
+
+    pub fn set_boundary_mode(&mut self, boundary_mode: BoundaryMode) {
+        self.boundary_mode = boundary_mode;
+    }
+
+    /// Draws all the particles as a single mesh instead of one primitive each.
+    pub fn set_batched(&mut self, batched: bool) {
+        self.batched = batched;
+    }
+
+    /// A new particle at a random position inside the container, and inside the mask when one
+    /// is set and the position is found in a few attempts.
+    fn respawn(&mut self) -> Particle {
+        let mut particle = self.spawn_inside();
+        for _ in 1..SPAWN_ATTEMPTS {
+            let roll = self.rng.gen();
+            if self.accepts_spawn(roll, Vec2::new(particle.x, particle.y)) {
+                break;
+            }
+            particle = self.spawn_inside();
+        }
+        particle
+    }
+
+    /// A new particle at a random position inside the container.
+    fn spawn_inside(&mut self) -> Particle {
+        random_particle(&mut self.rng, self.container, self.max_age, self.age_jitter)
+    }
+
+    /// Whether a particle may respawn at `position`, the crowded regions being rejected more
+    /// often the higher the respawn bias. `roll` is uniform between 0 and 1.
+    fn accepts_spawn(&self, roll: f32, position: Vec2) -> bool {
+        if let Some(mask) = &self.mask {
+            if !mask.contains(self.container, position) {
+                return false;
+            }
+        }
+        match self.density_cell(position) {
+            Some(cell) if self.respawn_bias > 0.0 => roll >= self.respawn_bias * self.density[cell],
+            _ => true,
+        }
+    }
+
+    fn density_rows(&self) -> usize {
+        ((DENSITY_COLUMNS as f32 * self.container.h() / self.container.w()).round() as usize).max(1)
+    }
+
+    /// Index of the density cell `position` falls in, if the grid is up to date and covers it.
+    fn density_cell(&self, position: Vec2) -> Option<usize> {
+        let rows = self.density_rows();
+        if self.density.len() != DENSITY_COLUMNS * rows || !self.container.contains(position) {
+            return None;
+        }
+        let column = ((position.x - self.container.left()) / self.container.w()
+            * DENSITY_COLUMNS as f32) as usize;
+        let row =
+            ((position.y - self.container.bottom()) / self.container.h() * rows as f32) as usize;
+        Some(row.min(rows - 1) * DENSITY_COLUMNS + column.min(DENSITY_COLUMNS - 1))
+    }
+
+    /// Counts the particles in each cell of the density grid.
+    fn update_density(&mut self) {
+        let mut density = mem::take(&mut self.density);
+        density.clear();
+        density.resize(DENSITY_COLUMNS * self.density_rows(), 0.0);
+        self.density = density;
+        for index in 0..self.particles.len() {
+            let particle = &self.particles[index];
+            if let Some(cell) = self.density_cell(Vec2::new(particle.x, particle.y)) {
+                self.density[cell] += 1.0;
+            }
+        }
+        let max = self.density.iter().copied().fold(0.0, f32::max).max(1.0);
+        for density in &mut self.density {
+            *density /= max;
+        }
+    }
+
+    /// Spawns a particle at each point of the emitter grid, replacing the particles in turn
+    /// once the count is reached.
+    fn emit(&mut self) {
+        let EmitterConfig {
+            grid_cols,
+            grid_rows,
+            ..
+        } = self.emitter;
+        for row in 0..grid_rows {
+            for col in 0..grid_cols {
+                let particle = Particle {
+                    x: self.container.left()
+                        + (col as f32 + 0.5) / grid_cols as f32 * self.container.w(),
+                    y: self.container.bottom()
+                        + (row as f32 + 0.5) / grid_rows as f32 * self.container.h(),
+                    ..self.spawn_inside()
+                };
+                if self.particles.len() < self.count {
+                    self.particles.push(particle);
+                } else if !self.particles.is_empty() {
+                    self.emitter_cursor %= self.particles.len();
+                    self.particles[self.emitter_cursor] = particle;
+                    self.emitter_cursor += 1;
+                }
+            }
+        }
+    }
+
+    /// Tapered particles need a limited lifetime, which they would otherwise outlive.
+    fn particle_size(&self, particle: &Particle) -> f32 {
+        if !self.size_taper || !self.limit_age {
+            return self.default_size;
+        }
+        let life = (particle.age / particle.max_age).clamp(0.0, 1.0);
+        let taper = (life.min(1.0 - life) / TAPER_FRACTION).min(1.0);
+        // Logarithmic, for the particles to quickly reach a visible size.
+        self.default_size * (1.0 + 9.0 * taper).log10()
+    }
+
+    fn particle_color(&self, particle: &Particle) -> LinSrgba {
+        let color = self.base_color(particle);
+        if !self.hue_cycle {
+            return color;
+        }
+        // Shifted without leaving the linear space, so that a null offset keeps the color.
+        let shifted = Hsv::from(color.color).shift_hue(self.hue_offset * 360.0);
+        LinSrgba {
+            color: LinSrgb::from(shifted),
+            alpha: color.alpha,
+        }
+    }
+
+    /// Color of a particle given by the color mode, before the hue cycle.
+    fn base_color(&self, particle: &Particle) -> LinSrgba {
+        match self.color_mode {
+            ParticleColorMode::Random => particle.color.into_lin_srgba(),
+            ParticleColorMode::Speed => self
+                .colormap
+                .sample(particle.velocity.length() / self.move_delta),
+            ParticleColorMode::Angle => self.colormap.sample_angle(particle.angle),
+            ParticleColorMode::Neighbors => self
+                .colormap
+                .sample(particle.neighbors as f32 / self.neighbors_max.max(1) as f32),
+        }
+    }
+
+    /// Counts the neighbors of each particle, bucketing the particles in a grid of cells as
+    /// large as the radius so that only the 9 cells around a particle are searched.
+    fn count_neighbors(&mut self) {
+        let radius = self.neighbor_radius;
+        let cell = |particle: &Particle| {
+            (
+                (particle.x / radius).floor() as i32,
+                (particle.y / radius).floor() as i32,
+            )
+        };
+        let mut grid: HashMap<(i32, i32), Vec<usize>> = HashMap::new();
+        for (index, particle) in self.particles.iter().enumerate() {
+            grid.entry(cell(particle)).or_default().push(index);
+        }
+        let counts: Vec<u32> = self
+            .particles
+            .par_iter()
+            .map(|particle| {
+                let (column, row) = cell(particle);
+                let mut count = 0;
+                for neighbor_column in column - 1..=column + 1 {
+                    for neighbor_row in row - 1..=row + 1 {
+                        if let Some(bucket) = grid.get(&(neighbor_column, neighbor_row)) {
+                            count += bucket
+                                .iter()
+                                .map(|&index| &self.particles[index])
+                                .filter(|other| {
+                                    (other.x - particle.x).powi(2) + (other.y - particle.y).powi(2)
+                                        < radius * radius
+                                })
+                                .count();
+                        }
+                    }
+                }
+                // The particle is its own neighbor.
+                count.saturating_sub(1) as u32
+            })
+            .collect();
+        self.neighbors_max = counts.iter().copied().max().unwrap_or(0);
+        for (particle, count) in self.particles.iter_mut().zip(counts) {
+            particle.neighbors = count;
+        }
+    }
+
+    /// Draws `particles` as a single mesh, matching the primitives `draw` issues otherwise.
+    fn draw_mesh(&self, draw: &Draw, particles: &[&Particle]) {
+        // Offsets of the vertices from the center of a particle of size 2, the first one of a
+        // circle being its center.
+        let (corners, triangles): (Vec<Vec2>, Vec<[usize; 3]>) = match self.shape {
+            ParticleShape::Square => (
+                vec![
+                    Vec2::new(-1.0, -1.0),
+                    Vec2::new(1.0, -1.0),
+                    Vec2::new(1.0, 1.0),
+                    Vec2::new(-1.0, 1.0),
+                ],
+                vec![[0, 1, 2], [0, 2, 3]],
+            ),
+            ParticleShape::Circle => (
+                iter::once(Vec2::ZERO)
+                    .chain((0..CIRCLE_SEGMENTS).map(|segment| {
+                        Vec2::new(1.0, 0.0)
+                            .rotate(segment as f32 / CIRCLE_SEGMENTS as f32 * 2.0 * PI)
+                    }))
+                    .collect(),
+                (0..CIRCLE_SEGMENTS)
+                    .map(|segment| [0, segment + 1, (segment + 1) % CIRCLE_SEGMENTS + 1])
+                    .collect(),
+            ),
+        };
+        let mut points = Vec::with_capacity(particles.len() * corners.len());
+        let mut indices = Vec::with_capacity(particles.len() * triangles.len() * 3);
+        for particle in particles {
+            let color = self.particle_color(particle);
+            let half_size = self.particle_size(particle) / 2.0;
+            let first = points.len();
+            points.extend(corners.iter().map(|corner| {
+                let vertex = Vec2::new(particle.x, particle.y) + *corner * half_size;
+                (vertex.extend(0.0), color)
+            }));
+            indices.extend(triangles.iter().flatten().map(|index| first + index));
+        }
+        draw.mesh().indexed_colored(points, indices);
+    }
+}
+
+/// Noise modulating the particle speed, seeded apart from the field noise of the same `seed`.
+fn speed_noise(seed: u32) -> SharedNoise {
+    Arc::new(Perlin::new().set_seed(seed ^ SPEED_NOISE_SEED_SALT))
+}
+
+/// A new particle at a random position inside `rect`, with a random color.
+///
+/// Its max age is spread around `max_age`, so that particles do not all die at once.
+fn random_particle(rng: &mut impl Rng, rect: Rect, max_age: f32, age_jitter: f32) -> Particle {
+    Particle {
+        x: rect.left() + rng.gen::<f32>() * rect.w(),
+        y: rect.bottom() + rng.gen::<f32>() * rect.h(),
+        color: Rgb::new(rng.gen(), rng.gen(), rng.gen()),
+        velocity: Vec2::ZERO,
+        angle: 0.0,
+        age: 0.0,
+        max_age: max_age * (1.0 + age_jitter * rng.gen_range(-1.0..=1.0)),
+        neighbors: 0,
+        trail: VecDeque::new(),
+    }
+}
+
+/// Moves a particle which crossed an edge of `container` to the opposite edge.
+fn wrap(particle: &mut Particle, container: Rect) {
+    let (x, y) = (particle.x, particle.y);
+    particle.x = container.left() + (particle.x - container.left()).rem_euclid(container.w());
+    particle.y = container.bottom() + (particle.y - container.bottom()).rem_euclid(container.h());
+    // The trail would cross the whole container.
+    if (x, y) != (particle.x, particle.y) {
+        particle.trail.clear();
+    }
+}
+
+/// Reflects a particle which crossed an edge of `container` back inside.
+fn reflect(particle: &mut Particle, container: Rect) {
+    if particle.x < container.left() {
+        particle.x = (2.0 * container.left() - particle.x).min(container.right());
+        particle.velocity.x = -particle.velocity.x;
+    } else if particle.x > container.right() {
+        particle.x = (2.0 * container.right() - particle.x).max(container.left());
+        particle.velocity.x = -particle.velocity.x;
+    }
+    if particle.y < container.bottom() {
+        particle.y = (2.0 * container.bottom() - particle.y).min(container.top());
+        particle.velocity.y = -particle.velocity.y;
+    } else if particle.y > container.top() {
+        particle.y = (2.0 * container.top() - particle.y).max(container.bottom());
+        particle.velocity.y = -particle.velocity.y;
+    }
+}
+
+/// Gradient of the field angle, in turns, at the given normalized position, in window space.
+///
+/// With the default noise settings, its magnitude is close to 1.
+fn gradient(field: &dyn FieldSource, params: &FieldParams, container: Rect, perlin: Vec2) -> Vec2 {
+    let h = GRADIENT_EPSILON;
+    let sample = |x: f32, y: f32| field.angle_at(x, y, params) / (2.0 * PI);
+    let du = (sample(perlin.x + h, perlin.y) - sample(perlin.x - h, perlin.y)) / (2.0 * h);
+    let dv = (sample(perlin.x, perlin.y + h) - sample(perlin.x, perlin.y - h)) / (2.0 * h);
+    // The normalized coordinates may grow toward the left or the bottom of the window, the
+    // window may not be square, and the field may be zoomed.
+    let direction = params.orientation.screen_direction() / params.viewport.zoom;
+    Vec2::new(
+        du * direction.x,
+        dv * direction.y * container.w() / container.h(),
+    )
+}
+
+/// Curl of the field angle at the given normalized position, in window space.
+///
+/// The curl is the gradient of the angle rotated by 90°, a divergence-free vector field.
+fn curl(field: &dyn FieldSource, params: &FieldParams, container: Rect, perlin: Vec2) -> Vec2 {
+    let gradient = gradient(field, params, container, perlin);
+    Vec2::new(gradient.y, -gradient.x)
+}
+
+/// Sum of the impulses `forces` give to a particle at `position`, fading quadratically with
+/// the distance.
+fn push(forces: &[ForceSource], position: Vec2) -> Vec2 {
+    forces
+        .iter()
+        .map(|force| {
+            let offset = position - force.position;
+            let distance = offset.length();
+            if distance >= force.radius || distance == 0.0 {
+                return Vec2::ZERO;
+            }
+            let falloff = (1.0 - distance / force.radius).powi(2);
+            offset / distance * force.strength * falloff
+        })
+        .sum()
+}
+
+fn is_culled(cull_rect: Option<Rect>, particle: &Particle) -> bool {
+    cull_rect.map_or(false, |rect| {
+        !rect.contains(Vec2::new(particle.x, particle.y))
+    })
 }
 
 impl ParticleSystem for SimpleParticleSystem {
     fn reset(&mut self) {
-        let mut particles = vec![];
+        self.rng = StdRng::seed_from_u64(self.seed);
+        self.particles.clear();
         for _ in 0..self.count {
-            let x = 1.5*random_range(self.container.left(), self.container.right());
-            let y = 1.5*random_range(self.container.bottom(), self.container.top());
-            particles.push(Particle {
-                x,
-                y,
-                color: Rgb::new(random(), random(), random()),
-            });
+            let particle = self.spawn_inside();
+            self.particles.push(particle);
         }
-        self.particles = particles;
     }
-    fn update(&mut self, noise_z: f32, frequency: f32, max_angle: Radian) {
-        for particle in &mut self.particles {
-            let perlin_x = (self.container.right() - particle.x) / self.container.w();
-            let perlin_y = (self.container.top() - particle.y) / self.container.h();
-
-            let noise_angle = self.noise.get([
-                (perlin_x * frequency) as f64,
-                (perlin_y * frequency) as f64,
-                noise_z as f64,
-            ]) as f32
-                * max_angle;
-            let gradient = Vec2::new(1., 0.).rotate(noise_angle) * self.move_delta;
-            particle.x += gradient.x;
-            particle.y += gradient.y;
+    fn update(
+        &mut self,
+        field: &dyn FieldSource,
+        params: &FieldParams,
+        forces: &[ForceSource],
+        dt: f32,
+    ) {
+        if self.hue_cycle {
+            self.hue_offset = (self.hue_offset + self.hue_cycle_speed * dt).rem_euclid(1.0);
+        }
+        if self.respawn_bias > 0.0 {
+            self.update_density();
+        }
+        if self.emitting {
+            self.emitter_lag += self.emitter.rate * dt;
+            while self.emitter_lag >= 1.0 {
+                self.emitter_lag -= 1.0;
+                self.emit();
+            }
+        }
+        let cull_rect = self.cull_rect();
+        let trail_capacity = self.trail_capacity();
+        // Taken out of the system while they move, for the update to read its other fields.
+        let mut particles = mem::take(&mut self.particles);
+        // Velocity given by the field at a window position, with the field angle there.
+        let field_velocity = |position: Vec2| -> (Vec2, Radian) {
+            let perlin = normalized_position(self.container, position, params);
+            let move_delta = self.move_delta * self.speed_factor(perlin, params);
+            if self.behavior == Behavior::Advect && !self.use_curl {
+                let angle = field_angle(field, self.container, position, params);
+                (Vec2::new(1., 0.).rotate(angle) * move_delta, angle)
+            } else {
+                let direction = match self.behavior {
+                    Behavior::Advect => curl(field, params, self.container, perlin),
+                    Behavior::Descend => -gradient(field, params, self.container, perlin),
+                    Behavior::Ascend => gradient(field, params, self.container, perlin),
+                };
+                let velocity = direction * move_delta;
+                (velocity, velocity.y.atan2(velocity.x))
+            }
+        };
+        // Particles read the states from before the update and only write their own fields, so
+        // the order they are updated in does not matter, should they read each other. Those to
+        // respawn are only flagged, to be respawned in order from the seeded generator.
+        let mut states = mem::take(&mut self.states);
+        states.clear();
+        states.extend(particles.iter().map(Particle::state));
+        let respawns: Vec<bool> = particles
+            .par_iter_mut()
+            .zip(states.par_iter())
+            .map(|(particle, state)| {
+                if is_culled(cull_rect, particle) {
+                    match self.cull_action {
+                        CullAction::Skip => {
+                            particle.age += dt;
+                            return self.limit_age && particle.age > particle.max_age;
+                        }
+                        CullAction::Respawn => return true,
+                    }
+                }
+                let position = state.position;
+                let (k1, angle) = field_velocity(position);
+                let target = match self.integration {
+                    Integration::Euler => k1,
+                    Integration::Heun => (k1 + field_velocity(position + k1 * dt).0) / 2.0,
+                    Integration::Rk4 => {
+                        let k2 = field_velocity(position + k1 * dt / 2.0).0;
+                        let k3 = field_velocity(position + k2 * dt / 2.0).0;
+                        let k4 = field_velocity(position + k3 * dt).0;
+                        (k1 + 2.0 * k2 + 2.0 * k3 + k4) / 6.0
+                    }
+                };
+                if trail_capacity > 0 {
+                    particle.trail.push_back(position);
+                }
+                while particle.trail.len() > trail_capacity {
+                    particle.trail.pop_front();
+                }
+                // Particles keep part of their momentum, overshooting when the field turns.
+                let velocity = state.velocity + (target - state.velocity) * self.steering;
+                particle.velocity = velocity * (1.0 - self.damping) + push(forces, position);
+                particle.x = position.x + particle.velocity.x * dt;
+                particle.y = position.y + particle.velocity.y * dt;
+                particle.angle = angle;
+                particle.age += dt;
+                if self.limit_age && particle.age > particle.max_age {
+                    return true;
+                }
+                match self.boundary_mode {
+                    BoundaryMode::Escape => (),
+                    BoundaryMode::Wrap => wrap(particle, self.container),
+                    BoundaryMode::Reflect => reflect(particle, self.container),
+                    BoundaryMode::Respawn => {
+                        if !self.container.contains(Vec2::new(particle.x, particle.y)) {
+                            return true;
+                        }
+                    }
+                }
+                match &self.mask {
+                    Some(mask) => !mask.contains(self.container, Vec2::new(particle.x, particle.y)),
+                    None => false,
+                }
+            })
+            .collect();
+        for (particle, respawn) in particles.iter_mut().zip(respawns) {
+            if respawn {
+                *particle = self.respawn();
+            }
+        }
+        self.particles = particles;
+        self.states = states;
+        if self.color_mode == ParticleColorMode::Neighbors {
+            self.count_neighbors();
         }
     }
     fn draw(&self, draw: &Draw) {
-        for particle in &self.particles {
-            draw.rect()
-                .color(particle.color)
-                .w(self.default_size)
-                .h(self.default_size)
-                .x_y(particle.x, particle.y);
+        let cull_rect = self.cull_rect();
+        let mut particles: Vec<&Particle> = self.particles.iter().collect();
+        match self.draw_order {
+            DrawOrder::Spawn => (),
+            DrawOrder::Age => particles.sort_by(|a, b| b.age.total_cmp(&a.age)),
+            DrawOrder::Y => particles.sort_by(|a, b| b.y.total_cmp(&a.y)),
+        }
+        particles.retain(|particle| !is_culled(cull_rect, particle));
+        self.draw_trails(draw, &particles);
+        if self.batched {
+            self.draw_mesh(draw, &particles);
+            return;
+        }
+        for particle in particles {
+            let color = self.particle_color(particle);
+            let size = self.particle_size(particle);
+            match self.shape {
+                ParticleShape::Square => {
+                    draw.rect()
+                        .color(color)
+                        .w(size)
+                        .h(size)
+                        .x_y(particle.x, particle.y);
+                }
+                ParticleShape::Circle => {
+                    draw.ellipse()
+                        .color(color)
+                        .w(size)
+                        .h(size)
+                        .x_y(particle.x, particle.y);
+                }
+            }
         }
     }
     fn config_gui(&mut self, ui: &mut egui::Ui) {
         ui.vertical(|ui| {
             ui.horizontal(|ui| {
-                ui.add(egui::DragValue::new(&mut self.count).speed(10));
+                if ui
+                    .add(egui::DragValue::new(&mut self.count).speed(10))
+                    .changed()
+                {
+                    self.set_count(self.count);
+                }
                 ui.label("particles");
             });
             ui.horizontal(|ui| {
                 ui.add(egui::DragValue::new(&mut self.move_delta));
-                ui.label("move delta");
+                ui.label("move delta (px/s)");
+                ui.add(
+                    egui::Slider::new(&mut self.speed_variation, 0.0..=1.0).text("speed variation"),
+                );
+            });
+            ui.horizontal(|ui| {
+                ui.add(egui::Slider::new(&mut self.steering, 0.01..=1.0).text("steering"));
+                ui.add(egui::Slider::new(&mut self.damping, 0.0..=0.5).text("damping"));
+            });
+            ui.horizontal(|ui| {
+                egui::ComboBox::from_id_source("Particle Behavior")
+                    .selected_text(format!("{:?}", self.behavior))
+                    .show_ui(ui, |ui| {
+                        ui.selectable_value(&mut self.behavior, Behavior::Advect, "Advect");
+                        ui.selectable_value(&mut self.behavior, Behavior::Descend, "Descend");
+                        ui.selectable_value(&mut self.behavior, Behavior::Ascend, "Ascend");
+                    });
+                ui.label("behavior");
+                ui.add_enabled(
+                    self.behavior == Behavior::Advect,
+                    egui::Checkbox::new(&mut self.use_curl, "curl noise"),
+                );
+            });
+            ui.horizontal(|ui| {
+                egui::ComboBox::from_id_source("Particle Integration")
+                    .selected_text(format!("{:?}", self.integration))
+                    .show_ui(ui, |ui| {
+                        ui.selectable_value(&mut self.integration, Integration::Euler, "Euler");
+                        ui.selectable_value(&mut self.integration, Integration::Heun, "Heun");
+                        ui.selectable_value(&mut self.integration, Integration::Rk4, "RK4");
+                    });
+                ui.label("integration");
             });
             ui.horizontal(|ui| {
                 ui.add(egui::DragValue::new(&mut self.default_size).clamp_range(0.0..=100.0));
                 ui.label("size");
+                ui.add_enabled(
+                    self.limit_age,
+                    egui::Checkbox::new(&mut self.size_taper, "taper with age"),
+                );
+                ui.checkbox(&mut self.batched, "batched");
+                egui::ComboBox::from_id_source("Particle Shape")
+                    .selected_text(format!("{:?}", self.shape))
+                    .show_ui(ui, |ui| {
+                        ui.selectable_value(&mut self.shape, ParticleShape::Square, "Square");
+                        ui.selectable_value(&mut self.shape, ParticleShape::Circle, "Circle");
+                    });
+            });
+            ui.horizontal(|ui| {
+                ui.add(egui::DragValue::new(&mut self.trail_len).clamp_range(0..=TRAIL_LEN_MAX));
+                ui.label("trail (positions)");
+                let capacity = self.trail_capacity();
+                if capacity < self.trail_len {
+                    ui.colored_label(
+                        egui::Color32::YELLOW,
+                        format!("shortened to {} for this many particles", capacity),
+                    );
+                }
+            });
+            ui.horizontal(|ui| {
+                egui::ComboBox::from_id_source("Particle Draw Order")
+                    .selected_text(format!("{:?}", self.draw_order))
+                    .show_ui(ui, |ui| {
+                        ui.selectable_value(&mut self.draw_order, DrawOrder::Spawn, "Spawn");
+                        ui.selectable_value(&mut self.draw_order, DrawOrder::Age, "Age");
+                        ui.selectable_value(&mut self.draw_order, DrawOrder::Y, "Y");
+                    });
+                ui.label("draw order");
+            });
+            ui.horizontal(|ui| {
+                egui::ComboBox::from_id_source("Particle Color Mode")
+                    .selected_text(format!("{:?}", self.color_mode))
+                    .show_ui(ui, |ui| {
+                        ui.selectable_value(
+                            &mut self.color_mode,
+                            ParticleColorMode::Random,
+                            "Random",
+                        );
+                        ui.selectable_value(
+                            &mut self.color_mode,
+                            ParticleColorMode::Speed,
+                            "Speed",
+                        );
+                        ui.selectable_value(
+                            &mut self.color_mode,
+                            ParticleColorMode::Angle,
+                            "Angle",
+                        );
+                        ui.selectable_value(
+                            &mut self.color_mode,
+                            ParticleColorMode::Neighbors,
+                            "Neighbors",
+                        );
+                    });
+                ui.label("color");
+                ui.add_enabled_ui(self.color_mode != ParticleColorMode::Random, |ui| {
+                    egui::ComboBox::from_id_source("Particle Colormap")
+                        .selected_text(format!("{:?}", self.colormap))
+                        .show_ui(ui, |ui| {
+                            for colormap in Colormap::ALL {
+                                ui.selectable_value(
+                                    &mut self.colormap,
+                                    colormap,
+                                    format!("{:?}", colormap),
+                                );
+                            }
+                        });
+                });
+                if self.color_mode == ParticleColorMode::Neighbors {
+                    ui.add(
+                        egui::Slider::new(&mut self.neighbor_radius, 1.0..=100.0)
+                            .text("radius (px)"),
+                    );
+                }
+            });
+            ui.horizontal(|ui| {
+                ui.checkbox(&mut self.hue_cycle, "hue cycle");
+                ui.add_enabled(
+                    self.hue_cycle,
+                    egui::Slider::new(&mut self.hue_cycle_speed, 0.0..=2.0).suffix("turn/s"),
+                );
+            });
+            ui.horizontal(|ui| {
+                egui::ComboBox::from_id_source("Particle Boundary Mode")
+                    .selected_text(format!("{:?}", self.boundary_mode))
+                    .show_ui(ui, |ui| {
+                        ui.selectable_value(
+                            &mut self.boundary_mode,
+                            BoundaryMode::Escape,
+                            "Escape",
+                        );
+                        ui.selectable_value(&mut self.boundary_mode, BoundaryMode::Wrap, "Wrap");
+                        ui.selectable_value(
+                            &mut self.boundary_mode,
+                            BoundaryMode::Reflect,
+                            "Reflect",
+                        );
+                        ui.selectable_value(
+                            &mut self.boundary_mode,
+                            BoundaryMode::Respawn,
+                            "Respawn",
+                        );
+                    });
+                ui.label("boundary");
+            });
+            ui.horizontal(|ui| {
+                ui.checkbox(&mut self.limit_age, "lifetime");
+                ui.add(
+                    egui::DragValue::new(&mut self.max_age)
+                        .speed(0.1)
+                        .clamp_range(0.1..=1000.0),
+                );
+                ui.label("max age (s)");
+                ui.add(egui::Slider::new(&mut self.age_jitter, 0.0..=1.0).text("jitter"));
+            });
+            ui.add(
+                egui::Slider::new(&mut self.respawn_bias, 0.0..=1.0)
+                    .text("respawn bias to empty regions"),
+            );
+            ui.horizontal(|ui| {
+                ui.checkbox(&mut self.emitting, "emitters");
+                ui.add(
+                    egui::DragValue::new(&mut self.emitter.rate)
+                        .speed(0.1)
+                        .clamp_range(0.1..=60.0),
+                );
+                ui.label("per second");
+                ui.add(egui::DragValue::new(&mut self.emitter.grid_cols).clamp_range(1..=64));
+                ui.label("x");
+                ui.add(egui::DragValue::new(&mut self.emitter.grid_rows).clamp_range(1..=64));
+                ui.label("grid");
+            });
+            ui.horizontal(|ui| {
+                ui.checkbox(&mut self.cull_offscreen, "cull off-screen");
+                ui.add(egui::DragValue::new(&mut self.cull_margin).clamp_range(0.0..=1000.0));
+                ui.label("margin");
+                egui::ComboBox::from_id_source("Particle Cull Action")
+                    .selected_text(format!("{:?}", self.cull_action))
+                    .show_ui(ui, |ui| {
+                        ui.selectable_value(&mut self.cull_action, CullAction::Skip, "Skip");
+                        ui.selectable_value(&mut self.cull_action, CullAction::Respawn, "Respawn");
+                    });
             });
         });
     }
+    fn nearest(&self, point: Vec2) -> Option<ParticleInfo> {
+        self.particles
+            .iter()
+            .min_by(|a, b| {
+                let dist_a = point.distance_squared(Vec2::new(a.x, a.y));
+                let dist_b = point.distance_squared(Vec2::new(b.x, b.y));
+                dist_a.total_cmp(&dist_b)
+            })
+            .map(|particle| ParticleInfo {
+                position: Vec2::new(particle.x, particle.y),
+                velocity: particle.velocity,
+                age: particle.age,
+            })
+    }
+    fn positions(&self) -> Box<dyn Iterator<Item = Vec2> + '_> {
+        Box::new(
+            self.particles
+                .iter()
+                .map(|particle| Vec2::new(particle.x, particle.y)),
+        )
+    }
+    fn snapshot(&self) -> Vec<(Vec2, rgb::Srgb<u8>)> {
+        self.particles
+            .iter()
+            .map(|particle| (Vec2::new(particle.x, particle.y), particle.color))
+            .collect()
+    }
+    fn config(&self) -> ParticleSettings {
+        ParticleSettings {
+            count: self.count,
+            move_delta: self.move_delta,
+            size: self.default_size,
+            trail_len: self.trail_len,
+            shape: self.shape,
+            draw_order: self.draw_order,
+            color_mode: self.color_mode,
+            boundary_mode: self.boundary_mode,
+            integration: self.integration,
+            steering: self.steering,
+            damping: self.damping,
+            cull_offscreen: self.cull_offscreen,
+            cull_margin: self.cull_margin,
+            cull_action: self.cull_action,
+            limit_age: self.limit_age,
+            max_age: self.max_age,
+            age_jitter: self.age_jitter,
+            emitting: self.emitting,
+            emitter: self.emitter,
+        }
+    }
+    fn apply_config(&mut self, settings: &ParticleSettings) {
+        self.move_delta = settings.move_delta;
+        self.default_size = settings.size.clamp(0.0, 100.0);
+        self.trail_len = settings.trail_len.min(TRAIL_LEN_MAX);
+        self.shape = settings.shape;
+        self.draw_order = settings.draw_order;
+        self.color_mode = settings.color_mode;
+        self.boundary_mode = settings.boundary_mode;
+        self.integration = settings.integration;
+        self.steering = settings.steering.clamp(0.01, 1.0);
+        self.damping = settings.damping.clamp(0.0, 0.5);
+        self.cull_offscreen = settings.cull_offscreen;
+        self.cull_margin = settings.cull_margin.clamp(0.0, 1000.0);
+        self.cull_action = settings.cull_action;
+        self.limit_age = settings.limit_age;
+        self.max_age = settings.max_age.clamp(0.1, 1000.0);
+        self.age_jitter = settings.age_jitter.clamp(0.0, 1.0);
+        self.emitting = settings.emitting;
+        self.emitter = EmitterConfig {
+            rate: settings.emitter.rate.clamp(0.1, 60.0),
+            grid_cols: settings.emitter.grid_cols.clamp(1, 64),
+            grid_rows: settings.emitter.grid_rows.clamp(1, 64),
+        };
+        self.set_count(settings.count);
+    }
+    fn count(&self) -> usize {
+        self.particles.len()
+    }
+    fn set_count(&mut self, count: usize) {
+        self.count = count;
+        if count <= self.particles.len() {
+            self.particles.truncate(count);
+        } else {
+            let missing = count - self.particles.len();
+            for _ in 0..missing {
+                let particle = self.respawn();
+                self.particles.push(particle);
+            }
+        }
+    }
+    fn spawn_at(&mut self, center: Vec2, count: usize) {
+        let count = count.min(SPAWN_COUNT_MAX.saturating_sub(self.particles.len()));
+        for position in spawn_positions(&mut self.rng, center, count) {
+            let particle = Particle {
+                x: position.x,
+                y: position.y,
+                ..self.spawn_inside()
+            };
+            self.particles.push(particle);
+        }
+        self.count = self.particles.len();
+    }
+    fn load_positions(&mut self, particles: Vec<(Vec2, rgb::Srgb<u8>)>) {
+        let container = self.container;
+        self.count = particles.len();
+        self.particles.clear();
+        for (position, color) in particles {
+            let particle = Particle {
+                x: position.x.clamp(container.left(), container.right()),
+                y: position.y.clamp(container.bottom(), container.top()),
+                color,
+                ..self.spawn_inside()
+            };
+            self.particles.push(particle);
+        }
+    }
+    fn set_seed(&mut self, seed: u64) {
+        self.seed = seed;
+        self.speed_noise = speed_noise(seed as u32);
+    }
+    /// Particles keep their position relative to the container, so that they spread over the
+    /// new one. They are respawned when the previous container was empty.
+    fn set_container(&mut self, container: Rect) {
+        let previous = self.container;
+        if previous.w() < 1.0 || previous.h() < 1.0 {
+            self.container = container;
+            let mut particles = mem::take(&mut self.particles);
+            for particle in &mut particles {
+                *particle = self.respawn();
+            }
+            self.particles = particles;
+            return;
+        }
+        let rescale = |point: Vec2| {
+            Vec2::new(
+                container.left() + (point.x - previous.left()) / previous.w() * container.w(),
+                container.bottom() + (point.y - previous.bottom()) / previous.h() * container.h(),
+            )
+        };
+        for particle in &mut self.particles {
+            let position = rescale(Vec2::new(particle.x, particle.y));
+            particle.x = position.x;
+            particle.y = position.y;
+            for point in &mut particle.trail {
+                *point = rescale(*point);
+            }
+        }
+        self.container = container;
+    }
+    fn set_mask(&mut self, mask: Option<Mask>) {
+        self.mask = mask;
+    }
 }
@@ -0,0 +1,376 @@
+/*
+MIT License
+
+Copyright (c) 2023 Vincent Hiribarren
+
+Permission is hereby granted, free of charge, to any person obtaining a copy
+of this software and associated documentation files (the "Software"), to deal
+in the Software without restriction, including without limitation the rights
+to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+copies of the Software, and to permit persons to whom the Software is
+furnished to do so, subject to the following conditions:
+
+The above copyright notice and this permission notice shall be included in all
+copies or substantial portions of the Software.
+
+THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+SOFTWARE.
+*/
+
+use std::fmt::{self, Display, Write};
+
+use serde::{ser, Serialize};
+
+const INDENT: &str = "    ";
+
+/// Formats `value` as a Rust expression building it, e.g. a struct literal.
+///
+/// Structs are written one field per line, sequences as `vec!` and tuples, which include the
+/// arrays, as array literals. Strings are converted with `into`, for `String` fields.
+pub fn to_string<T: Serialize + ?Sized>(value: &T) -> Result<String, Error> {
+    let mut serializer = Serializer::default();
+    value.serialize(&mut serializer)?;
+    Ok(serializer.output)
+}
+
+#[derive(Debug)]
+pub struct Error(String);
+
+impl Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl ser::Error for Error {
+    fn custom<T: Display>(msg: T) -> Self {
+        Error(msg.to_string())
+    }
+}
+
+#[derive(Default)]
+struct Serializer {
+    output: String,
+    depth: usize,
+}
+
+impl Serializer {
+    fn write(&mut self, code: impl Display) {
+        write!(self.output, "{}", code).unwrap();
+    }
+
+    fn write_float(&mut self, value: f64, kind: &str) {
+        if value.is_nan() {
+            self.write(format_args!("{}::NAN", kind));
+        } else if value.is_infinite() {
+            let sign = if value > 0.0 { "" } else { "NEG_" };
+            self.write(format_args!("{}::{}INFINITY", kind, sign));
+        } else {
+            self.write(format_args!("{:?}", value));
+        }
+    }
+
+    /// Separates the items of an inline list, `open` ending the text before the first one.
+    fn separate(&mut self, open: char) {
+        if !self.output.ends_with(open) {
+            self.write(", ");
+        }
+    }
+
+    fn new_line(&mut self) {
+        self.write('\n');
+        for _ in 0..self.depth {
+            self.write(INDENT);
+        }
+    }
+
+    fn open_struct(&mut self, name: impl Display) {
+        self.write(format_args!("{} {{", name));
+        self.depth += 1;
+    }
+
+    fn close_struct(&mut self) {
+        self.depth -= 1;
+        self.new_line();
+        self.write('}');
+    }
+}
+
+impl ser::Serializer for &mut Serializer {
+    type Ok = ();
+    type Error = Error;
+    type SerializeSeq = Self;
+    type SerializeTuple = Self;
+    type SerializeTupleStruct = Self;
+    type SerializeTupleVariant = Self;
+    type SerializeMap = Self;
+    type SerializeStruct = Self;
+    type SerializeStructVariant = Self;
+
+    fn serialize_bool(self, v: bool) -> Result<(), Error> {
+        self.write(v);
+        Ok(())
+    }
+    fn serialize_i8(self, v: i8) -> Result<(), Error> {
+        self.serialize_i64(v as i64)
+    }
+    fn serialize_i16(self, v: i16) -> Result<(), Error> {
+        self.serialize_i64(v as i64)
+    }
+    fn serialize_i32(self, v: i32) -> Result<(), Error> {
+        self.serialize_i64(v as i64)
+    }
+    fn serialize_i64(self, v: i64) -> Result<(), Error> {
+        self.write(v);
+        Ok(())
+    }
+    fn serialize_u8(self, v: u8) -> Result<(), Error> {
+        self.serialize_u64(v as u64)
+    }
+    fn serialize_u16(self, v: u16) -> Result<(), Error> {
+        self.serialize_u64(v as u64)
+    }
+    fn serialize_u32(self, v: u32) -> Result<(), Error> {
+        self.serialize_u64(v as u64)
+    }
+    fn serialize_u64(self, v: u64) -> Result<(), Error> {
+        self.write(v);
+        Ok(())
+    }
+    fn serialize_f32(self, v: f32) -> Result<(), Error> {
+        // Through `Debug` on the `f32` itself, for the shortest literal giving it back.
+        if v.is_finite() {
+            self.write(format_args!("{:?}", v));
+        } else {
+            self.write_float(v as f64, "f32");
+        }
+        Ok(())
+    }
+    fn serialize_f64(self, v: f64) -> Result<(), Error> {
+        self.write_float(v, "f64");
+        Ok(())
+    }
+    fn serialize_char(self, v: char) -> Result<(), Error> {
+        self.write(format_args!("{:?}", v));
+        Ok(())
+    }
+    fn serialize_str(self, v: &str) -> Result<(), Error> {
+        self.write(format_args!("{:?}.into()", v));
+        Ok(())
+    }
+    fn serialize_bytes(self, v: &[u8]) -> Result<(), Error> {
+        self.write(format_args!("vec!{:?}", v));
+        Ok(())
+    }
+    fn serialize_none(self) -> Result<(), Error> {
+        self.write("None");
+        Ok(())
+    }
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<(), Error> {
+        self.write("Some(");
+        value.serialize(&mut *self)?;
+        self.write(')');
+        Ok(())
+    }
+    fn serialize_unit(self) -> Result<(), Error> {
+        self.write("()");
+        Ok(())
+    }
+    fn serialize_unit_struct(self, name: &'static str) -> Result<(), Error> {
+        self.write(name);
+        Ok(())
+    }
+    fn serialize_unit_variant(
+        self,
+        name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+    ) -> Result<(), Error> {
+        self.write(format_args!("{}::{}", name, variant));
+        Ok(())
+    }
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        name: &'static str,
+        value: &T,
+    ) -> Result<(), Error> {
+        self.write(format_args!("{}(", name));
+        value.serialize(&mut *self)?;
+        self.write(')');
+        Ok(())
+    }
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+        value: &T,
+    ) -> Result<(), Error> {
+        self.write(format_args!("{}::{}(", name, variant));
+        value.serialize(&mut *self)?;
+        self.write(')');
+        Ok(())
+    }
+    fn serialize_seq(self, _len: Option<usize>) -> Result<Self, Error> {
+        self.write("vec![");
+        Ok(self)
+    }
+    fn serialize_tuple(self, _len: usize) -> Result<Self, Error> {
+        self.write('[');
+        Ok(self)
+    }
+    fn serialize_tuple_struct(self, name: &'static str, _len: usize) -> Result<Self, Error> {
+        self.write(format_args!("{}(", name));
+        Ok(self)
+    }
+    fn serialize_tuple_variant(
+        self,
+        name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+        _len: usize,
+    ) -> Result<Self, Error> {
+        self.write(format_args!("{}::{}(", name, variant));
+        Ok(self)
+    }
+    fn serialize_map(self, _len: Option<usize>) -> Result<Self, Error> {
+        self.write("HashMap::from([");
+        Ok(self)
+    }
+    fn serialize_struct(self, name: &'static str, _len: usize) -> Result<Self, Error> {
+        self.open_struct(name);
+        Ok(self)
+    }
+    fn serialize_struct_variant(
+        self,
+        name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+        _len: usize,
+    ) -> Result<Self, Error> {
+        self.open_struct(format_args!("{}::{}", name, variant));
+        Ok(self)
+    }
+}
+
+impl ser::SerializeSeq for &mut Serializer {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        self.separate('[');
+        value.serialize(&mut **self)
+    }
+    fn end(self) -> Result<(), Error> {
+        self.write(']');
+        Ok(())
+    }
+}
+
+impl ser::SerializeTuple for &mut Serializer {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        self.separate('[');
+        value.serialize(&mut **self)
+    }
+    fn end(self) -> Result<(), Error> {
+        self.write(']');
+        Ok(())
+    }
+}
+
+impl ser::SerializeTupleStruct for &mut Serializer {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        self.separate('(');
+        value.serialize(&mut **self)
+    }
+    fn end(self) -> Result<(), Error> {
+        self.write(')');
+        Ok(())
+    }
+}
+
+impl ser::SerializeTupleVariant for &mut Serializer {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        self.separate('(');
+        value.serialize(&mut **self)
+    }
+    fn end(self) -> Result<(), Error> {
+        self.write(')');
+        Ok(())
+    }
+}
+
+impl ser::SerializeMap for &mut Serializer {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<(), Error> {
+        self.separate('[');
+        self.write('(');
+        key.serialize(&mut **self)
+    }
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        self.write(", ");
+        value.serialize(&mut **self)?;
+        self.write(')');
+        Ok(())
+    }
+    fn end(self) -> Result<(), Error> {
+        self.write("])");
+        Ok(())
+    }
+}
+
+impl ser::SerializeStruct for &mut Serializer {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), Error> {
+        self.new_line();
+        self.write(format_args!("{}: ", key));
+        value.serialize(&mut **self)?;
+        self.write(',');
+        Ok(())
+    }
+    fn end(self) -> Result<(), Error> {
+        self.close_struct();
+        Ok(())
+    }
+}
+
+impl ser::SerializeStructVariant for &mut Serializer {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), Error> {
+        ser::SerializeStruct::serialize_field(self, key, value)
+    }
+    fn end(self) -> Result<(), Error> {
+        self.close_struct();
+        Ok(())
+    }
+}
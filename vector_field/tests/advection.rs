@@ -0,0 +1,88 @@
+/*
+MIT License
+
+Copyright (c) 2023 Vincent Hiribarren
+
+Permission is hereby granted, free of charge, to any person obtaining a copy
+of this software and associated documentation files (the "Software"), to deal
+in the Software without restriction, including without limitation the rights
+to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+copies of the Software, and to permit persons to whom the Software is
+furnished to do so, subject to the following conditions:
+
+The above copyright notice and this permission notice shall be included in all
+copies or substantial portions of the Software.
+
+THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+SOFTWARE.
+*/
+
+use std::f32::consts::FRAC_PI_4;
+
+use nannou::{
+    color::Srgb,
+    geom::{Rect, Vec2},
+    noise::NoiseFn,
+};
+use vector_field::{
+    field::{FieldParams, NoiseField},
+    particles::{simple::SimpleParticleSystem, ParticleSystem},
+};
+
+const DT: f32 = 1.0 / 60.0;
+/// Largest error on a particle displacement, in pixels.
+const TOLERANCE: f32 = 1e-3;
+
+/// Noise giving the same value everywhere.
+struct ConstantNoise(f64);
+
+impl NoiseFn<[f64; 2]> for ConstantNoise {
+    fn get(&self, _point: [f64; 2]) -> f64 {
+        self.0
+    }
+}
+
+impl NoiseFn<[f64; 4]> for ConstantNoise {
+    fn get(&self, _point: [f64; 4]) -> f64 {
+        self.0
+    }
+}
+
+#[test]
+fn particles_advect_along_constant_field() {
+    let container = Rect::from_w_h(800.0, 600.0);
+    let mut system = SimpleParticleSystem::new(container);
+    // Away from the edges, so that no particle is respawned.
+    let start: Vec<Vec2> = (0..5)
+        .flat_map(|i| (0..5).map(move |j| Vec2::new(i as f32 * 50.0, j as f32 * 50.0) - 100.0))
+        .collect();
+    system.load_positions(
+        start
+            .iter()
+            .map(|&position| (position, Srgb::new(255, 255, 255)))
+            .collect(),
+    );
+    let noise = ConstantNoise(0.125);
+    let field = NoiseField::new(&noise);
+    let params = FieldParams::default();
+    system.update(&field, &params, &[], DT);
+
+    let expected = Vec2::new(1.0, 0.0).rotate(FRAC_PI_4) * system.config().move_delta * DT;
+    let end: Vec<Vec2> = system.positions().collect();
+    assert_eq!(end.len(), start.len());
+    for (start, end) in start.iter().zip(end) {
+        let displacement = end - *start;
+        assert!(
+            displacement.distance(expected) < TOLERANCE,
+            "particle at {:?} moved by {:?} instead of {:?}",
+            start,
+            displacement,
+            expected
+        );
+    }
+}
@@ -0,0 +1,84 @@
+/*
+MIT License
+
+Copyright (c) 2023 Vincent Hiribarren
+
+Permission is hereby granted, free of charge, to any person obtaining a copy
+of this software and associated documentation files (the "Software"), to deal
+in the Software without restriction, including without limitation the rights
+to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+copies of the Software, and to permit persons to whom the Software is
+furnished to do so, subject to the following conditions:
+
+The above copyright notice and this permission notice shall be included in all
+copies or substantial portions of the Software.
+
+THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+SOFTWARE.
+*/
+
+use nannou::{geom::Rect, noise::Perlin};
+use vector_field::{
+    field::{FieldParams, NoiseField},
+    particles::{
+        simple::{BoundaryMode, SimpleParticleSystem},
+        ParticleSettings, ParticleSystem,
+    },
+};
+
+/// In pixels per second, crossing a good part of the container at each update.
+const WRAP_MOVE_DELTA: f32 = 20_000.0;
+const WRAP_UPDATES: usize = 200;
+
+#[test]
+fn particles_stay_inside_container_after_update() {
+    let container = Rect::from_w_h(800.0, 600.0);
+    let mut system = SimpleParticleSystem::new(container);
+    let noise = Perlin::new();
+    let field = NoiseField::new(&noise);
+    for update in 0..10 {
+        let params = FieldParams {
+            z: update as f32 * 0.01,
+            ..FieldParams::default()
+        };
+        system.update(&field, &params, &[], 1.0 / 60.0);
+        assert!(system
+            .positions()
+            .all(|position| container.contains(position)));
+    }
+}
+
+#[test]
+fn particles_stay_inside_container_when_wrapping() {
+    // Spanning negative to positive coordinates, as the window does.
+    let container = Rect::from_x_y_w_h(-30.0, 20.0, 800.0, 600.0);
+    let mut system = SimpleParticleSystem::new(container);
+    system.set_boundary_mode(BoundaryMode::Wrap);
+    system.apply_config(&ParticleSettings {
+        move_delta: WRAP_MOVE_DELTA,
+        ..system.config()
+    });
+    let noise = Perlin::new();
+    let field = NoiseField::new(&noise);
+    for update in 0..WRAP_UPDATES {
+        let params = FieldParams {
+            z: update as f32 * 0.01,
+            ..FieldParams::default()
+        };
+        system.update(&field, &params, &[], 1.0 / 60.0);
+        for position in system.positions() {
+            assert!(
+                container.contains(position),
+                "particle at {:?} outside {:?} after {} updates",
+                position,
+                container,
+                update + 1
+            );
+        }
+    }
+}
@@ -0,0 +1,62 @@
+/*
+MIT License
+
+Copyright (c) 2023 Vincent Hiribarren
+
+Permission is hereby granted, free of charge, to any person obtaining a copy
+of this software and associated documentation files (the "Software"), to deal
+in the Software without restriction, including without limitation the rights
+to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+copies of the Software, and to permit persons to whom the Software is
+furnished to do so, subject to the following conditions:
+
+The above copyright notice and this permission notice shall be included in all
+copies or substantial portions of the Software.
+
+THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+SOFTWARE.
+*/
+
+//! Compares drawing the particles one primitive each with drawing them as a single mesh.
+//!
+//! Only the recording into `Draw` is timed, the tessellation of the primitives needing a
+//! device. Run with `cargo test --release --test draw_bench -- --ignored --nocapture`.
+
+use std::time::{Duration, Instant};
+
+use nannou::{geom::Rect, Draw};
+use vector_field::particles::{simple::SimpleParticleSystem, ParticleSystem};
+
+const PARTICLE_COUNT: usize = 20_000;
+const FRAMES: usize = 20;
+
+fn time_draws(system: &SimpleParticleSystem) -> Duration {
+    let start = Instant::now();
+    for _ in 0..FRAMES {
+        let draw = Draw::new();
+        system.draw(&draw);
+    }
+    start.elapsed()
+}
+
+#[test]
+#[ignore]
+fn batched_draw_timing() {
+    let container = Rect::from_w_h(1920.0, 1080.0);
+    let mut system = SimpleParticleSystem::new(container);
+    system.set_count(PARTICLE_COUNT);
+
+    system.set_batched(false);
+    let per_particle = time_draws(&system);
+    system.set_batched(true);
+    let batched = time_draws(&system);
+    println!(
+        "{} particles, {} frames: {:?} one primitive each, {:?} as a single mesh",
+        PARTICLE_COUNT, FRAMES, per_particle, batched
+    );
+}
@@ -0,0 +1,77 @@
+/*
+MIT License
+
+Copyright (c) 2023 Vincent Hiribarren
+
+Permission is hereby granted, free of charge, to any person obtaining a copy
+of this software and associated documentation files (the "Software"), to deal
+in the Software without restriction, including without limitation the rights
+to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+copies of the Software, and to permit persons to whom the Software is
+furnished to do so, subject to the following conditions:
+
+The above copyright notice and this permission notice shall be included in all
+copies or substantial portions of the Software.
+
+THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+SOFTWARE.
+*/
+
+//! Compares sampling the noise field at every arrow of a 5 pixel grid with sampling a field
+//! grid baked from it.
+//!
+//! Run with `cargo test --release --test field_grid_bench -- --ignored --nocapture`.
+
+use std::{
+    hint::black_box,
+    time::{Duration, Instant},
+};
+
+use nannou::noise::Perlin;
+use vector_field::field::{FieldGrid, FieldParams, FieldSource, NoiseField};
+
+const WINDOW_SIZE: [usize; 2] = [1280, 720];
+const STEP: usize = 5;
+const GRID_COLUMNS: usize = 256;
+const FRAMES: usize = 20;
+
+fn time_arrows(field: &dyn FieldSource, params: &FieldParams) -> Duration {
+    let [width, height] = WINDOW_SIZE;
+    let start = Instant::now();
+    for _ in 0..FRAMES {
+        for x in (0..width).step_by(STEP) {
+            for y in (0..height).step_by(STEP) {
+                black_box(field.angle_at(
+                    x as f32 / width as f32,
+                    y as f32 / height as f32,
+                    params,
+                ));
+            }
+        }
+    }
+    start.elapsed()
+}
+
+#[test]
+#[ignore]
+fn field_grid_timing() {
+    let noise = Perlin::new();
+    let field = NoiseField::new(&noise);
+    let params = FieldParams::default();
+    let rows = GRID_COLUMNS * WINDOW_SIZE[1] / WINDOW_SIZE[0];
+
+    let sampled = time_arrows(&field, &params);
+    let start = Instant::now();
+    let grid = FieldGrid::bake(&field, &params, GRID_COLUMNS, rows);
+    let baking = start.elapsed();
+    let cached = time_arrows(&grid, &params);
+    println!(
+        "step {}, {} frames: {:?} sampling the noise, {:?} sampling a {}x{} grid baked in {:?}",
+        STEP, FRAMES, sampled, cached, GRID_COLUMNS, rows, baking
+    );
+}
@@ -0,0 +1,53 @@
+/*
+MIT License
+
+Copyright (c) 2023 Vincent Hiribarren
+
+Permission is hereby granted, free of charge, to any person obtaining a copy
+of this software and associated documentation files (the "Software"), to deal
+in the Software without restriction, including without limitation the rights
+to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+copies of the Software, and to permit persons to whom the Software is
+furnished to do so, subject to the following conditions:
+
+The above copyright notice and this permission notice shall be included in all
+copies or substantial portions of the Software.
+
+THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+SOFTWARE.
+*/
+
+use nannou::geom::Rect;
+use vector_field::particles::{simple::SimpleParticleSystem, ParticleSystem};
+
+/// Largest distance from a corner of the enlarged container to its nearest particle. Particles
+/// are spread uniformly, so one lies much closer unless they stayed in the small container.
+const CORNER_DISTANCE_MAX: f32 = 100.0;
+
+#[test]
+fn particles_spread_over_enlarged_container() {
+    let small = Rect::from_w_h(100.0, 100.0);
+    let large = Rect::from_w_h(1000.0, 1000.0);
+    let mut system = SimpleParticleSystem::new(small);
+    system.set_container(large);
+    let corners = [
+        large.top_left(),
+        large.top_right(),
+        large.bottom_left(),
+        large.bottom_right(),
+    ];
+    for corner in corners {
+        let nearest = system.nearest(corner).unwrap().position;
+        assert!(
+            nearest.distance(corner) < CORNER_DISTANCE_MAX,
+            "no particle near {:?}, nearest at {:?}",
+            corner,
+            nearest
+        );
+    }
+}
@@ -0,0 +1,78 @@
+/*
+MIT License
+
+Copyright (c) 2023 Vincent Hiribarren
+
+Permission is hereby granted, free of charge, to any person obtaining a copy
+of this software and associated documentation files (the "Software"), to deal
+in the Software without restriction, including without limitation the rights
+to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+copies of the Software, and to permit persons to whom the Software is
+furnished to do so, subject to the following conditions:
+
+The above copyright notice and this permission notice shall be included in all
+copies or substantial portions of the Software.
+
+THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+SOFTWARE.
+*/
+
+//! Compares the particle update on a single thread with the parallel one.
+//!
+//! Run with `cargo test --release --test update_bench -- --ignored --nocapture`.
+
+use std::time::{Duration, Instant};
+
+use nannou::{geom::Rect, noise::Perlin};
+use vector_field::{
+    field::{FieldParams, NoiseField},
+    particles::{simple::SimpleParticleSystem, ParticleSystem},
+};
+
+const PARTICLE_COUNT: usize = 100_000;
+const UPDATES: usize = 20;
+
+fn time_updates(system: &mut SimpleParticleSystem) -> Duration {
+    let noise = Perlin::new();
+    let field = NoiseField::new(&noise);
+    let start = Instant::now();
+    for update in 0..UPDATES {
+        let params = FieldParams {
+            z: update as f32 * 0.01,
+            ..FieldParams::default()
+        };
+        system.update(&field, &params, &[], 1.0 / 60.0);
+    }
+    start.elapsed()
+}
+
+#[test]
+#[ignore]
+fn parallel_update_is_faster() {
+    let container = Rect::from_w_h(1920.0, 1080.0);
+    let mut system = SimpleParticleSystem::new(container);
+    system.set_count(PARTICLE_COUNT);
+
+    let single_thread = rayon::ThreadPoolBuilder::new()
+        .num_threads(1)
+        .build()
+        .unwrap();
+    let sequential = single_thread.install(|| time_updates(&mut system));
+    let parallel = time_updates(&mut system);
+    println!(
+        "{} particles, {} updates: {:?} on one thread, {:?} on {} threads",
+        PARTICLE_COUNT,
+        UPDATES,
+        sequential,
+        parallel,
+        rayon::current_num_threads()
+    );
+    if rayon::current_num_threads() > 1 {
+        assert!(parallel < sequential);
+    }
+}